@@ -10,22 +10,21 @@ struct Uniforms {
 }
 
 fn main() {
-    let mut tracker = usch::sync::Tracker::new(140
+    let tracker = usch::sync::Tracker::new(140
         , Some(std::path::Path::new("examples/01-raymarch/sync.json"))
         , &["foo"]
-    );
-
-    tracker.set_value("foo", 4, 4.5);
+    ).unwrap();
 
     DemoBuilder::new((1920, 1080), false, "01 Raymarch")
         .with_tracker(tracker)
-        .with_ogg_music(include_bytes!("music.ogg"), Some(743006))
+        .with_streamed_ogg_music(include_bytes!("music.ogg"))
         .scene(|builder| {
             builder
-                .with_uniforms(|time| {
+                .with_uniforms(|context| {
+                    let (width, height) = context.resolution();
                     bytemuck::bytes_of(&Uniforms {
-                        resolution: [1920f32, 1080f32, 0f32],
-                        time: time.elapsed().as_secs_f32(),
+                        resolution: [width as f32, height as f32, 0f32],
+                        time: context.elapsed().as_secs_f32(),
                     })
                     .to_vec()
                 })
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use winit::window::Fullscreen;
 
-use crate::{time::TimeSource, *};
+use crate::*;
 
 pub struct DemoBuilder {
     pub(super) demo: Demo,
@@ -26,6 +26,7 @@ impl DemoBuilder {
         if fullscreen {
             window.set_cursor_visible(false)
         }
+        let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
         let surface = unsafe { instance.create_surface(&window) };
@@ -37,38 +38,34 @@ impl DemoBuilder {
         }))
         .unwrap();
 
-        let (device, queue) = block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: wgpu::Features::empty(),
-                limits: wgpu::Limits::default(),
-            },
-            None,
-        ))
-        .unwrap();
+        let (device, queue) = DemoBuilder::request_device(&adapter);
+        let format = surface.get_preferred_format(&adapter).unwrap();
 
         #[cfg(feature = "editor")]
-        let ui = ui::Ui::new(
-            &window,
-            &device,
-            surface.get_preferred_format(&adapter).unwrap(),
-        );
+        let ui = ui::Ui::new(&window, &device, format);
 
         DemoBuilder {
             demo: Demo {
-                event_loop,
-                window,
+                display: Some(Display {
+                    event_loop,
+                    window,
+                    surface,
+                    #[cfg(feature = "editor")]
+                    ui: ui,
+                }),
+                size: (size.width, size.height),
                 device,
                 queue,
-                surface,
-                adapter,
+                format,
                 music: None,
                 scenes: vec![],
                 time: SeekableTimeSource::now(),
-                #[cfg(feature = "editor")]
+                fixed_step: None,
+                capture: None,
+                headless: None,
                 tracker: None,
                 #[cfg(feature = "editor")]
-                ui: ui,
+                rocket: None,
             },
         }
     }
@@ -93,6 +90,18 @@ impl DemoBuilder {
         self.demo
     }
 
+    pub(crate) fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        ))
+        .unwrap()
+    }
+
     fn fullscreen_mode(
         event_loop: &EventLoop<()>,
         resolution: PhysicalSize<u32>,
@@ -122,13 +131,13 @@ pub struct SceneBuilder<'a> {
     fragment_source_watcher: Option<SourceWatcher>,
     #[cfg(feature = "editor")]
     glsl_include_paths: Option<Vec<PathBuf>>,
-    uniforms: Box<dyn Fn(&dyn TimeSource) -> Vec<u8>>,
+    uniforms: Box<dyn Fn(&UniformContext) -> Vec<u8>>,
 }
 
 impl<'a> SceneBuilder<'a> {
     pub fn with_uniforms(
         mut self,
-        uniforms: impl Fn(&dyn TimeSource) -> Vec<u8> + 'static,
+        uniforms: impl Fn(&UniformContext) -> Vec<u8> + 'static,
     ) -> SceneBuilder<'a> {
         self.uniforms = Box::new(uniforms);
         self
@@ -183,7 +192,7 @@ impl<'a> SceneBuilder<'a> {
                 &demo.device,
                 demo.get_preferred_format(),
                 frag,
-                &(self.uniforms)(&demo.time),
+                &(self.uniforms)(&UniformContext::new(&demo.time, demo.tracker.as_ref(), demo.resolution())),
             ),
             #[cfg(feature = "editor")]
             fragment_source_watcher: self.fragment_source_watcher,
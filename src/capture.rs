@@ -0,0 +1,270 @@
+//! Rendering of frames offscreen at a fixed frame rate, saved as numbered images or streamed to an
+//! encoder, to record demos that are too heavy to run in real time.
+
+use std::{io::{self, Write}, path::{Path, PathBuf}, process::{Child, Command, Stdio}, time::Duration};
+
+use futures::executor::block_on;
+
+use crate::{DemoBuilder, music::Music, time::FixedStepTimeSource};
+
+mod image;
+mod qoi;
+mod wav;
+
+pub use image::ImageFormat;
+
+impl DemoBuilder {
+    /// Renders the demo frame by frame at the frame rate of the capture rather than in real time,
+    /// whatever is given to `with_fixed_step`, and exits once every frame has been saved. Music is not
+    /// played while capturing.
+    pub fn with_capture(mut self, capture: Capture) -> DemoBuilder {
+        self.demo.capture = Some(capture);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Output {
+    Images { directory: PathBuf, format: ImageFormat },
+    Process { program: String, args: Vec<String> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Capture {
+    output: Output,
+    resolution: (u32, u32),
+    fps: u32,
+    length: Duration,
+    audio: Option<PathBuf>,
+}
+
+impl Capture {
+    /// Captures `length` of the demo at `fps` into `directory`, which is created if needed.
+    pub fn new(directory: impl Into<PathBuf>, resolution: (u32, u32), fps: u32, length: Duration) -> Capture {
+        Capture {
+            output: Output::Images { directory: directory.into(), format: ImageFormat::default() },
+            resolution,
+            fps,
+            length,
+            audio: None,
+        }
+    }
+
+    /// Captures `length` of the demo at `fps` by writing the frames to the standard input of a
+    /// program, such as ffmpeg. Frames are written back to back as raw 8-bit RGBA, top row first, so
+    /// for ffmpeg the arguments would start with `-f rawvideo -pix_fmt rgba -s 1920x1080 -r 60 -i -`.
+    pub fn to_process(
+        program: impl Into<String>,
+        args: &[&str],
+        resolution: (u32, u32),
+        fps: u32,
+        length: Duration,
+    ) -> Capture {
+        Capture {
+            output: Output::Process { program: program.into(), args: args.iter().map(|x| x.to_string()).collect() },
+            resolution,
+            fps,
+            length,
+            audio: None,
+        }
+    }
+
+    /// Sets the format of the image files, which does not apply when capturing to a program.
+    pub fn with_format(mut self, format: ImageFormat) -> Capture {
+        match &mut self.output {
+            Output::Images { format: current, .. } => *current = format,
+            Output::Process { .. } => (),
+        }
+        self
+    }
+
+    /// Also writes the music to a WAV file, cut or padded with silence to exactly the length of the
+    /// captured frames.
+    pub fn with_audio(mut self, path: impl Into<PathBuf>) -> Capture {
+        self.audio = Some(path.into());
+        self
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    pub fn frames(&self) -> u64 {
+        (self.length.as_secs_f64() * self.fps as f64).ceil() as u64
+    }
+
+    /// How many samples per channel cover the captured frames, rounded to the closest sample.
+    fn samples(&self, sample_rate: u32) -> usize {
+        ((self.frames() * sample_rate as u64 * 2 + self.fps as u64) / (self.fps as u64 * 2)) as usize
+    }
+
+    fn write_audio(&self, path: &Path, music: &Music) -> io::Result<()> {
+        let mut samples = music.samples().into_owned();
+        samples.resize(self.samples(music.sample_rate) * music.channels as usize, 0);
+
+        let file = io::BufWriter::new(std::fs::File::create(path)?);
+        wav::write(file, music.sample_rate, music.channels, &samples)
+    }
+}
+
+/// The offscreen target that frames are rendered to, and the buffer they are read back through.
+pub(crate) struct Recorder {
+    capture: Capture,
+    /// The program frames are written to, if any
+    process: Option<Child>,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    /// Rows of the buffer are padded to the alignment required for copies
+    bytes_per_row: u32,
+    bgra: bool,
+    /// Time of the frame being captured, which only moves on once it has been saved
+    time: FixedStepTimeSource,
+}
+
+impl Recorder {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        capture: &Capture,
+        music: Option<&Music>,
+    ) -> io::Result<Recorder> {
+        match (&capture.audio, music) {
+            (Some(path), Some(music)) => capture.write_audio(path, music)?,
+            (Some(_), None) => return Err(io::Error::new(io::ErrorKind::NotFound, "There is no music to capture")),
+            (None, _) => (),
+        }
+
+        let process = match &capture.output {
+            Output::Images { directory, .. } => {
+                std::fs::create_dir_all(directory)?;
+                None
+            },
+            Output::Process { program, args } => Some(Command::new(program).args(args).stdin(Stdio::piped()).spawn()?),
+        };
+
+        let (width, height) = capture.resolution;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("capture"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (width * 4 + alignment - 1) / alignment * alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("capture"),
+            size: (bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Recorder {
+            capture: capture.clone(),
+            process,
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            buffer,
+            bytes_per_row,
+            bgra: matches!(format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb),
+            time: FixedStepTimeSource::new(capture.fps),
+        })
+    }
+
+    /// How many frames are captured in all.
+    pub fn frames(&self) -> u64 {
+        self.capture.frames()
+    }
+
+    /// The time to render the next frame at.
+    pub fn time(&self) -> &FixedStepTimeSource {
+        &self.time
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Copies the rendered frame into the readback buffer, after the passes drawing it.
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        let (width, height) = self.capture.resolution;
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(self.bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+    }
+
+    /// Waits for the copied frame and saves it, returning true once every frame has been saved.
+    pub fn save(&mut self, device: &wgpu::Device) -> io::Result<bool> {
+        let frame = self.time.frame();
+        let (width, height) = self.capture.resolution;
+        let slice = self.buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).map_err(|err| io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to read back frame {}: {:?}", frame, err),
+        ))?;
+
+        let rgba = image::to_rgba(&slice.get_mapped_range(), width, height, self.bytes_per_row, self.bgra);
+        self.buffer.unmap();
+
+        match (&self.capture.output, &mut self.process) {
+            (Output::Images { directory, format }, _) => {
+                let path = directory.join(format!("{:06}.{}", frame, format.extension()));
+                format.write(&path, width, height, &rgba)?;
+            },
+            (Output::Process { .. }, Some(process)) => process.stdin.as_mut().unwrap().write_all(&rgba)?,
+            (Output::Process { .. }, None) => unreachable!(),
+        }
+
+        self.time.advance();
+        let done = self.time.frame() >= self.capture.frames();
+        if done {
+            self.finish()?;
+        }
+        Ok(done)
+    }
+
+    /// Closes the input of the program frames are written to, and waits for it to exit. This is done
+    /// once every frame has been saved, and must be done when the capture is stopped before that, in
+    /// which case an error tells how far it got.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.process.take() {
+            Some(mut process) => {
+                drop(process.stdin.take());
+                let status = process.wait()?;
+                if !status.success() {
+                    return Err(io::Error::new(io::ErrorKind::Other, format!("The encoder exited with {}", status)));
+                }
+            },
+            None => (),
+        }
+
+        let (saved, frames) = (self.time.frame(), self.capture.frames());
+        if saved < frames {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!("The capture was stopped after {} of {} frames", saved, frames),
+            ));
+        }
+        Ok(())
+    }
+}
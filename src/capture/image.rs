@@ -0,0 +1,77 @@
+//! Conversion of frames read back from the GPU into image files.
+
+use std::{io, path::Path};
+
+use super::qoi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageFormat {
+    #[default]
+    Qoi,
+    #[cfg(feature = "png")]
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Qoi => "qoi",
+            #[cfg(feature = "png")]
+            ImageFormat::Png => "png",
+        }
+    }
+
+    /// Writes 8-bit RGBA pixels to `path`.
+    pub(super) fn write(&self, path: &Path, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+        match self {
+            ImageFormat::Qoi => std::fs::write(path, qoi::encode(width, height, rgba)),
+            #[cfg(feature = "png")]
+            ImageFormat::Png => {
+                let file = io::BufWriter::new(std::fs::File::create(path)?);
+                let mut encoder = png::Encoder::new(file, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+                encoder
+                    .write_header()
+                    .and_then(|mut writer| writer.write_image_data(rgba))
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+            },
+        }
+    }
+}
+
+/// Turns rows of pixels padded to `bytes_per_row` into tightly packed RGBA, swapping the red and blue
+/// channels of BGRA data.
+pub(super) fn to_rgba(data: &[u8], width: u32, height: u32, bytes_per_row: u32, bgra: bool) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+    for row in data.chunks(bytes_per_row as usize).take(height as usize) {
+        for pixel in row[..(width * 4) as usize].chunks_exact(4) {
+            if bgra {
+                rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+            } else {
+                rgba.extend_from_slice(pixel);
+            }
+        }
+    }
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_bgra_rows_are_converted() {
+        let data = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0,
+            9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+        ];
+
+        assert_eq!(
+            vec![3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16],
+            to_rgba(&data, 2, 2, 12, true),
+        );
+        assert_eq!(data[..8], to_rgba(&data, 2, 1, 12, false)[..]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Encoder for the [Quite OK Image](https://qoiformat.org/) format, which compresses about as well as
+//! PNG while being fast enough to keep up with capturing every frame.
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// Encodes 8-bit sRGB pixels with alpha, given row by row from the top left.
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(14 + rgba.len() / 2 + END_MARKER.len());
+    data.extend_from_slice(b"qoif");
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.push(4);
+    data.push(0);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+
+    let pixels = rgba.chunks_exact(4);
+    let count = pixels.len();
+    for (n, pixel) in pixels.enumerate() {
+        let pixel: [u8; 4] = pixel.try_into().unwrap();
+
+        if pixel == previous {
+            run += 1;
+            if run == 62 || n + 1 == count {
+                data.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            data.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let hash = (pixel[0] as usize * 3 + pixel[1] as usize * 5 + pixel[2] as usize * 7 + pixel[3] as usize * 11) % 64;
+        if index[hash] == pixel {
+            data.push(OP_INDEX | hash as u8);
+        } else if pixel[3] == previous[3] {
+            index[hash] = pixel;
+
+            // Differences wrap around, so that going from 255 to 0 is a difference of 1
+            let dr = pixel[0].wrapping_sub(previous[0]) as i8;
+            let dg = pixel[1].wrapping_sub(previous[1]) as i8;
+            let db = pixel[2].wrapping_sub(previous[2]) as i8;
+            let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+
+            if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                data.push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+            } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                data.push(OP_LUMA | (dg + 32) as u8);
+                data.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+            } else {
+                data.extend_from_slice(&[OP_RGB, pixel[0], pixel[1], pixel[2]]);
+            }
+        } else {
+            index[hash] = pixel;
+            data.push(OP_RGBA);
+            data.extend_from_slice(&pixel);
+        }
+
+        previous = pixel;
+    }
+
+    data.extend_from_slice(&END_MARKER);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_are_encoded_with_the_smallest_op() {
+        let pixels = [
+            [0, 0, 0, 255],     // Same as the initial previous pixel, a run
+            [0, 0, 0, 255],
+            [1, 0, 255, 255],   // Small difference, wrapping around
+            [11, 10, 9, 255],   // Luma difference
+            [200, 100, 50, 255],
+            [200, 100, 50, 128],
+            [1, 0, 255, 255],   // Seen before, at index 49
+        ];
+        let data = encode(7, 1, &pixels.concat());
+
+        assert_eq!(b"qoif", &data[..4]);
+        assert_eq!([0, 0, 0, 7, 0, 0, 0, 1, 4, 0], data[4..14]);
+        assert_eq!(
+            vec![
+                OP_RUN | 1,
+                OP_DIFF | 3 << 4 | 2 << 2 | 1,
+                OP_LUMA | 42, 8 << 4 | 8,
+                OP_RGB, 200, 100, 50,
+                OP_RGBA, 200, 100, 50, 128,
+                OP_INDEX | 49,
+            ],
+            data[14..data.len() - 8],
+        );
+        assert_eq!(END_MARKER, data[data.len() - 8..]);
+    }
+}
@@ -0,0 +1,48 @@
+//! Writer for 16-bit PCM WAV files.
+
+use std::io::{self, Write};
+
+/// Writes interleaved samples with a canonical 44 byte header.
+pub fn write(mut writer: impl Write, sample_rate: u32, channels: u16, samples: &[i16]) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_describes_samples() {
+        let mut data = Vec::new();
+        write(&mut data, 44100, 2, &[1, -1]).unwrap();
+
+        assert_eq!(48, data.len());
+        assert_eq!(b"RIFF", &data[..4]);
+        assert_eq!(40u32.to_le_bytes(), data[4..8]);
+        assert_eq!(44100u32.to_le_bytes(), data[24..28]);
+        assert_eq!((44100u32 * 4).to_le_bytes(), data[28..32]);
+        assert_eq!(4u32.to_le_bytes(), data[40..44]);
+        assert_eq!([1, 0, 0xff, 0xff], data[44..]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Rendering without a window, for running demos on machines that have no display such as build
+//! servers. Frames are drawn to an offscreen texture, or to the capture when there is one.
+
+use futures::executor::block_on;
+
+use crate::{clear_pass, Demo, DemoBuilder, UniformContext, time::{FixedStepTimeSource, SeekableTimeSource}};
+
+pub(crate) struct Headless {
+    /// How many frames to render before exiting, unless there is a capture
+    pub frames: u64,
+}
+
+impl DemoBuilder {
+    /// Creates a demo that renders `frames` frames offscreen at 60 frames per second, or the rate
+    /// given to `with_fixed_step`, and then exits without opening a window or playing music. With a
+    /// capture, exactly the frames of the capture are rendered instead. A software adapter is used if
+    /// there is no GPU. The backend can be chosen with the `WGPU_BACKEND` environment variable.
+    pub fn headless((width, height): (u32, u32), frames: u64) -> DemoBuilder {
+        let backends = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
+        let instance = wgpu::Instance::new(backends);
+
+        let request_adapter = |force_fallback_adapter| block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter,
+            compatible_surface: None,
+        }));
+        let adapter = request_adapter(false)
+            .or_else(|| request_adapter(true))
+            .expect("No graphics adapter found, not even a software one");
+
+        #[cfg(debug_assertions)]
+        println!("Rendering headless on {}", adapter.get_info().name);
+
+        let (device, queue) = DemoBuilder::request_device(&adapter);
+
+        DemoBuilder {
+            demo: Demo {
+                display: None,
+                size: (width, height),
+                device,
+                queue,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                music: None,
+                scenes: vec![],
+                time: SeekableTimeSource::now(),
+                fixed_step: Some(FixedStepTimeSource::new(60)),
+                capture: None,
+                headless: Some(Headless { frames }),
+                tracker: None,
+                #[cfg(feature = "editor")]
+                rocket: None,
+            },
+        }
+    }
+}
+
+impl Demo {
+    /// Renders every frame as fast as possible, or every frame of the capture when there is one.
+    pub(crate) fn run_headless(mut self) {
+        let mut recorder = self.recorder();
+        let frames = match &recorder {
+            Some(recorder) => recorder.frames(),
+            None => self.headless.as_ref().unwrap().frames,
+        };
+
+        let (width, height) = self.resolution();
+        let target = self.device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("headless"),
+                size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        for _ in 0..frames {
+            let active_scene = self.scenes.first().unwrap();
+            let context = match (&recorder, &self.fixed_step) {
+                (Some(recorder), _) => UniformContext::new(recorder.time(), self.tracker.as_ref(), (width, height)),
+                (None, Some(time)) => UniformContext::new(time, self.tracker.as_ref(), (width, height)),
+                (None, None) => UniformContext::new(&self.time, self.tracker.as_ref(), (width, height)),
+            };
+            active_scene.update(&self.queue, &context);
+
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            {
+                let view = match &recorder {
+                    Some(recorder) => recorder.view(),
+                    None => &target,
+                };
+                let mut rpass = clear_pass(&mut encoder, view);
+                active_scene.draw(&mut rpass);
+            }
+            match &recorder {
+                Some(recorder) => recorder.copy(&mut encoder),
+                None => (),
+            }
+            self.queue.submit(Some(encoder.finish()));
+
+            match &mut recorder {
+                Some(recorder) => match recorder.save(&self.device) {
+                    Ok(false) => (),
+                    Ok(true) => break,
+                    Err(err) => {
+                        println!("Failed to capture frame: {}", err);
+                        break;
+                    },
+                },
+                // Wait for each frame, rather than queueing up work faster than it can be done
+                None => self.device.poll(wgpu::Maintain::Wait),
+            }
+
+            match &mut self.fixed_step {
+                Some(time) => time.advance(),
+                None => (),
+            }
+        }
+
+        // Waits for the encoder, and tells if a frame failed to be saved
+        match &mut recorder {
+            Some(recorder) => match recorder.finish() {
+                Ok(()) => (),
+                Err(err) => println!("Failed to capture frames: {}", err),
+            },
+            None => (),
+        }
+    }
+}
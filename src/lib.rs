@@ -1,8 +1,8 @@
-use std::{borrow::Cow, time::Instant, sync::{Arc, Mutex}, path::PathBuf};
+use std::{borrow::Cow, sync::mpsc, time::{Duration, Instant}, path::PathBuf};
 
 use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, SampleFormat, Stream, SupportedBufferSize, BufferSize};
 use futures::executor::block_on;
-use time::{SeekableTimeSource, TimeSource};
+use time::{FixedStepTimeSource, SeekableTimeSource, TimeSource};
 use winit::{
     dpi::PhysicalSize,
     event_loop::{ControlFlow, EventLoop},
@@ -15,62 +15,99 @@ use source_watcher::SourceWatcher;
 
 mod buffertypes;
 mod builders;
+pub mod capture;
 mod glsl;
+mod headless;
 pub mod music;
 mod raymarching;
 #[cfg(feature = "editor")]
 mod source_watcher;
 pub mod sync;
-mod time;
+pub mod time;
 #[cfg(feature = "editor")]
 pub mod ui;
 
 pub struct Demo {
-    event_loop: EventLoop<()>,
-    window: Window,
+    /// The window being rendered to, or `None` when rendering headless
+    display: Option<Display>,
+    /// Size of the window, or of the frames rendered headless
+    size: (u32, u32),
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface,
-    adapter: wgpu::Adapter,
-    music: Option<Arc<Mutex<music::Music>>>,
+    /// Format of the frames being rendered, which pipelines are built for
+    format: wgpu::TextureFormat,
+    music: Option<music::Music>,
     scenes: Vec<Scene>,
     time: SeekableTimeSource,
-    #[cfg(feature = "editor")]
+    /// Replaces `time` when rendering must be reproducible
+    fixed_step: Option<FixedStepTimeSource>,
+    capture: Option<capture::Capture>,
+    headless: Option<headless::Headless>,
     tracker: Option<sync::Tracker>,
     #[cfg(feature = "editor")]
+    rocket: Option<sync::rocket::Client>,
+}
+
+struct Display {
+    event_loop: EventLoop<()>,
+    window: Window,
+    surface: wgpu::Surface,
+    #[cfg(feature = "editor")]
     ui: ui::Ui,
 }
 
 impl Demo {
     pub fn run(mut self) {
-        let _stream = match self.music {
-            Some(_) => {
-                let stream = self.init_music().unwrap();
-                stream.play().unwrap();
-                Some(stream)
-            }
-            None => None,
+        match self.display.take() {
+            Some(display) => self.run_windowed(display),
+            None => self.run_headless(),
+        }
+    }
+
+    fn run_windowed(mut self, display: Display) {
+        let Display {
+            event_loop,
+            window,
+            surface,
+            #[cfg(feature = "editor")]
+            mut ui,
+        } = display;
+
+        let stream = match self.music {
+            Some(_) if self.capture.is_none() => match self.init_music() {
+                Ok(stream) => Some(stream),
+                Err(err) => {
+                    println!("Failed to play music: {}", err);
+                    None
+                },
+            },
+            _ => None,
         };
 
-        let size = self.window.inner_size();
+        let size = window.inner_size();
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: self.surface.get_preferred_format(&self.adapter).unwrap(),
+            format: self.format,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Mailbox,
         };
-        self.surface.configure(&self.device, &config);
+        surface.configure(&self.device, &config);
 
         #[cfg(feature=  "editor")]
         let start_time = Instant::now();
         self.time = SeekableTimeSource::now();
+        let mut audio_time = match &stream {
+            Some(_) => self.music.as_ref().map(|x| x.time_source()),
+            None => None,
+        };
+        let mut recorder = self.recorder();
 
-        self.event_loop.run(move |event, _, control_flow| {
+        event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Poll;
 
             #[cfg(feature = "editor")]
-            self.ui.handle_event(&start_time.elapsed(), &event);
+            ui.handle_event(&start_time.elapsed(), &event);
 
             match event {
                 winit::event::Event::WindowEvent {
@@ -85,20 +122,56 @@ impl Demo {
                             ..
                         },
                     window_id,
-                } if window_id == self.window.id() => *control_flow = ControlFlow::Exit,
+                } if window_id == window.id() => *control_flow = ControlFlow::Exit,
                 winit::event::Event::RedrawRequested(_) => {
+                    match self.music.as_ref().and_then(|x| x.error()) {
+                        Some(err) => {
+                            println!("The music stopped playing: {}", err);
+                            audio_time = None;
+                        },
+                        None => (),
+                    }
+
+                    // Follow the music while it plays, rather than letting the two clocks drift apart
+                    match &audio_time {
+                        Some(audio_time) if !self.time.is_paused() => self.time.seek(audio_time.elapsed()),
+                        _ => (),
+                    }
+
+                    // Wrap around the loop region before drawing, so that the frame matches the music
+                    #[cfg(feature = "editor")]
+                    match &mut self.tracker {
+                        Some(tracker) => {
+                            tracker.time = self.time.clone();
+                            if tracker.wrap_loop() {
+                                self.time = tracker.time.clone();
+                                match &mut self.music {
+                                    Some(music) => music.seek(&self.time.elapsed()),
+                                    None => (),
+                                }
+                            }
+                        },
+                        None => (),
+                    }
+
+                    let resolution = match &self.capture {
+                        Some(capture) => capture.resolution(),
+                        None => (size.width, size.height),
+                    };
                     let active_scene = self.scenes.first_mut().unwrap();
 
+                    let context = match (&recorder, &self.fixed_step) {
+                        (Some(recorder), _) => UniformContext::new(recorder.time(), self.tracker.as_ref(), resolution),
+                        (None, Some(time)) => UniformContext::new(time, self.tracker.as_ref(), resolution),
+                        (None, None) => UniformContext::new(&self.time, self.tracker.as_ref(), resolution),
+                    };
+
                     #[cfg(feature = "editor")]
-                    active_scene.reload_shaders_if_requested(
-                        &self.device,
-                        &self.time,
-                        self.surface.get_preferred_format(&self.adapter).unwrap(),
-                    );
+                    active_scene.reload_shaders_if_requested(&self.device, &context, self.format);
 
-                    active_scene.update(&self.queue, &self.time);
+                    active_scene.update(&self.queue, &context);
 
-                    let frame = self.surface.get_current_texture().unwrap();
+                    let frame = surface.get_current_texture().unwrap();
                     let view = frame
                         .texture
                         .create_view(&wgpu::TextureViewDescriptor::default());
@@ -106,26 +179,48 @@ impl Demo {
                         .device
                         .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
                     {
-                        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                            label: None,
-                            color_attachments: &[wgpu::RenderPassColorAttachment {
-                                view: &view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                    store: true,
-                                },
-                            }],
-                            depth_stencil_attachment: None,
-                        });
-
+                        let mut rpass = clear_pass(&mut encoder, &view);
                         active_scene.draw(&mut rpass);
                     }
 
+                    // Captured frames are drawn again offscreen, at their own resolution and without the editor
+                    match &recorder {
+                        Some(recorder) => {
+                            {
+                                let mut rpass = clear_pass(&mut encoder, recorder.view());
+                                active_scene.draw(&mut rpass);
+                            }
+                            recorder.copy(&mut encoder);
+                        },
+                        None => (),
+                    }
+
                     #[cfg(feature = "editor")]
                     {
                         self.tracker.as_mut().unwrap().time = self.time.clone();
-                        self.ui.draw(&self.window
+
+                        match &mut self.rocket {
+                            Some(rocket) => {
+                                let tracker = self.tracker.as_mut().unwrap();
+                                match rocket.update(tracker) {
+                                    Ok(true) => match &mut self.music {
+                                        Some(music) => {
+                                            music.set_paused(tracker.time.is_paused());
+                                            music.seek(&tracker.time.elapsed());
+                                        },
+                                        None => (),
+                                    },
+                                    Ok(false) => (),
+                                    Err(err) => {
+                                        println!("Lost connection to the Rocket editor: {}", err);
+                                        self.rocket = None;
+                                    },
+                                }
+                            },
+                            None => (),
+                        }
+
+                        ui.draw(&window
                             , &self.device
                             , &self.queue
                             , &mut encoder
@@ -138,69 +233,203 @@ impl Demo {
 
                     self.queue.submit(Some(encoder.finish()));
                     frame.present();
+
+                    match &mut recorder {
+                        Some(recorder) => match recorder.save(&self.device) {
+                            Ok(false) => (),
+                            Ok(true) => *control_flow = ControlFlow::Exit,
+                            Err(err) => {
+                                println!("Failed to capture frame: {}", err);
+                                *control_flow = ControlFlow::Exit;
+                            },
+                        },
+                        None => (),
+                    }
+
+                    match &mut self.fixed_step {
+                        Some(time) => time.advance(),
+                        None => (),
+                    }
                 }
                 winit::event::Event::MainEventsCleared => {
-                    self.window.request_redraw();
+                    window.request_redraw();
                 }
+                // Also reached when the capture is stopped early, so that the encoder gets to finish
+                winit::event::Event::LoopDestroyed => match &mut recorder {
+                    Some(recorder) => match recorder.finish() {
+                        Ok(()) => (),
+                        Err(err) => println!("Failed to capture frames: {}", err),
+                    },
+                    None => (),
+                },
                 _ => (),
             }
         });
     }
 
     pub fn get_preferred_format(&self) -> wgpu::TextureFormat {
-        self.surface.get_preferred_format(&self.adapter).unwrap()
+        self.format
+    }
+
+    /// The size of the frames being rendered, which are those of the capture when there is one.
+    pub fn resolution(&self) -> (u32, u32) {
+        match &self.capture {
+            Some(capture) => capture.resolution(),
+            None => self.size,
+        }
     }
 
-    fn init_music(&self) -> Option<Stream> {
-        match &self.music {
+    fn recorder(&self) -> Option<capture::Recorder> {
+        match &self.capture {
+            Some(capture) => Some(capture::Recorder::new(&self.device, self.format, capture, self.music.as_ref())
+                .expect("Failed to set up frame capture")),
             None => None,
-            Some(music) => {
-                let music = music.as_ref().lock().unwrap();
-                let host = cpal::default_host();
-                let device = host.default_output_device().unwrap();
-                let supported_config = device
-                    .supported_output_configs()
-                    .unwrap()
-                    .find(|x| x.channels() == 2
-                        && x.min_sample_rate().0 <= music.sample_rate
-                        && x.max_sample_rate().0 >= music.sample_rate
-                        && x.sample_format() == SampleFormat::F32
-                    )
-                    .expect(&format!("No audio output device supporting {} sample rate found", music.sample_rate))
-                    .with_sample_rate(cpal::SampleRate(music.sample_rate));
-                let mut config = supported_config.config();
-
-                // Use the smallest supported buffer size during editing for consistent scrubbing
-                #[cfg(feature = "editor")]
-                match supported_config.buffer_size() {
-                    SupportedBufferSize::Range
-                    {
-                        min,
-                        max: _
-                    } => config.buffer_size = BufferSize::Fixed(*min),
-                    SupportedBufferSize::Unknown => (),
-                }
+        }
+    }
 
-                device.build_output_stream(&config,
-                    {
-                        let music = self.music.as_ref().unwrap().clone();
-                        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                            match music.as_ref().lock() {
-                                Ok(mut music) => {
-                                    let samples = music.read(data.len());
-                                    for i in data.iter_mut().enumerate() {
-                                        *i.1 = cpal::Sample::from(&samples[i.0]);
-                                    }
-                                },
-                                Err(_) => panic!(),
-                            }
-                        }
-                    },
-                    move |_err| {
-                        panic!()
-                    },
-                ).ok()
+    /// Starts playing the music on the default audio device, moving its playback to the audio thread.
+    fn init_music(&mut self) -> Result<Stream, String> {
+        let music = self.music.as_mut().unwrap();
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or("No audio output device found")?;
+        // Any sample rate, number of channels and sample format will do, but ones matching the music
+        // are best
+        let supported_config = device
+            .supported_output_configs()
+            .map_err(|err| err.to_string())?
+            .max_by_key(|x| (
+                x.min_sample_rate().0 <= music.sample_rate && x.max_sample_rate().0 >= music.sample_rate,
+                x.channels() == music.channels,
+                x.sample_format() == SampleFormat::F32,
+            ))
+            .ok_or("The audio output device has no supported configurations")?;
+        let sample_rate = music.sample_rate.clamp(supported_config.min_sample_rate().0, supported_config.max_sample_rate().0);
+        let supported_config = supported_config.with_sample_rate(cpal::SampleRate(sample_rate));
+
+        #[cfg(debug_assertions)]
+        if sample_rate != music.sample_rate {
+            println!("Converting music from {} Hz to {} Hz for the audio device", music.sample_rate, sample_rate);
+        }
+        let mut config = supported_config.config();
+
+        // Use the smallest supported buffer size during editing for consistent scrubbing
+        #[cfg(feature = "editor")]
+        match supported_config.buffer_size() {
+            SupportedBufferSize::Range
+            {
+                min,
+                max: _
+            } => config.buffer_size = BufferSize::Fixed(*min),
+            SupportedBufferSize::Unknown => (),
+        }
+
+        // The playback is only handed to the stream once it is playing, so that it stays with the music
+        // and commands are still carried out if the stream fails to start
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let errors = music.error_sender();
+        let stream = match supported_config.sample_format() {
+            SampleFormat::F32 => build_output_stream::<f32>(&device, &config, receiver, errors),
+            SampleFormat::I16 => build_output_stream::<i16>(&device, &config, receiver, errors),
+            SampleFormat::U16 => build_output_stream::<u16>(&device, &config, receiver, errors),
+        }.map_err(|err| err.to_string())?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        let mut playback = music.take_playback().ok_or("The music is already playing")?;
+        playback.set_output_channels(config.channels as usize);
+        playback.set_output_rate(sample_rate);
+        sender.send(playback).map_err(|_| "The audio stream stopped before it got the music")?;
+        Ok(stream)
+    }
+}
+
+/// Plays music on the audio thread, converted to the sample format of the device.
+fn build_output_stream<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    receiver: mpsc::Receiver<music::Playback>,
+    errors: mpsc::Sender<String>,
+) -> Result<Stream, cpal::BuildStreamError> {
+    let mut playback = None;
+    device.build_output_stream(config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            // Silent until the playback has been handed over
+            if playback.is_none() {
+                playback = receiver.try_recv().ok();
+            }
+            let playback = match &mut playback {
+                Some(playback) => playback,
+                None => {
+                    data.iter_mut().for_each(|output| *output = cpal::Sample::from(&0i16));
+                    return;
+                },
+            };
+
+            let timestamp = info.timestamp();
+            playback.set_latency(timestamp.playback
+                .duration_since(&timestamp.callback)
+                .unwrap_or_default());
+
+            let samples = playback.read(data.len());
+            for (output, sample) in data.iter_mut().zip(samples) {
+                *output = cpal::Sample::from(sample);
             }
+        },
+        move |err| {
+            // Picked up by the demo on its next frame
+            errors.send(err.to_string()).ok();
+        },
+    )
+}
+
+fn clear_pass<'a>(encoder: &'a mut wgpu::CommandEncoder, view: &'a wgpu::TextureView) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: None,
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: None,
+    })
+}
+
+/// What the uniform callback of a [`Scene`] gets to work with each frame.
+pub struct UniformContext<'a> {
+    time: &'a dyn TimeSource,
+    tracker: Option<&'a sync::Tracker>,
+    resolution: (u32, u32),
+}
+
+impl<'a> UniformContext<'a> {
+    pub(crate) fn new(
+        time: &'a dyn TimeSource,
+        tracker: Option<&'a sync::Tracker>,
+        resolution: (u32, u32),
+    ) -> UniformContext<'a> {
+        UniformContext { time, tracker, resolution }
+    }
+
+    /// Width and height in pixels of the frame being rendered.
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    pub fn time(&self) -> &dyn TimeSource {
+        self.time
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.time.elapsed()
+    }
+
+    /// The value of a sync track at the current time, or zero if the demo has no tracker.
+    pub fn track(&self, name: &str) -> f32 {
+        match self.tracker {
+            Some(tracker) => tracker.value_at(name, self.time.elapsed()),
+            None => 0f32,
         }
     }
 }
@@ -211,12 +440,12 @@ pub struct Scene {
     fragment_source_watcher: Option<SourceWatcher>,
     #[cfg(feature = "editor")]
     glsl_include_paths: Option<Vec<PathBuf>>,
-    uniforms: Box<dyn Fn(&dyn TimeSource) -> Vec<u8>>,
+    uniforms: Box<dyn Fn(&UniformContext) -> Vec<u8>>,
 }
 
 impl Scene {
-    pub fn update(&self, queue: &wgpu::Queue, time: &dyn TimeSource) {
-        queue.write_buffer(&self.pipeline.uniform_buffer, 0, &(self.uniforms)(time));
+    pub fn update(&self, queue: &wgpu::Queue, context: &UniformContext) {
+        queue.write_buffer(&self.pipeline.uniform_buffer, 0, &(self.uniforms)(context));
     }
 
     pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
@@ -230,7 +459,7 @@ impl Scene {
     pub fn reload_shaders_if_requested(
         &mut self,
         device: &wgpu::Device,
-        time: &dyn TimeSource,
+        context: &UniformContext,
         format: wgpu::TextureFormat,
     ) {
         match &self.fragment_source_watcher {
@@ -246,7 +475,7 @@ impl Scene {
                         device,
                         format,
                         wgpu::ShaderSource::SpirV(Cow::Owned(shader.unwrap())),
-                        &(self.uniforms)(time),
+                        &(self.uniforms)(context),
                     )
                 }
                 None => (),
@@ -257,17 +486,17 @@ impl Scene {
 }
 
 mod binary {
-    use std::{io::{Read, Write}, mem::size_of};
+    use std::{io::{self, Read, Write}, mem::size_of};
 
-    use bytemuck::{from_bytes, bytes_of};
+    use bytemuck::{bytes_of, pod_read_unaligned};
 
-    pub fn read<T: bytemuck::Pod>(mut reader: impl Read) -> T {
-        let mut buf: Vec<u8> = Vec::with_capacity(size_of::<T>());
-        reader.read_exact(&mut buf).unwrap();
-        *from_bytes::<T>(&buf)
+    pub fn read<T: bytemuck::Pod>(mut reader: impl Read) -> io::Result<T> {
+        let mut buf = vec![0u8; size_of::<T>()];
+        reader.read_exact(&mut buf)?;
+        Ok(pod_read_unaligned::<T>(&buf))
     }
     
-    pub fn write<T: bytemuck::Pod>(mut writer: impl Write, value: &T) {
+    pub fn write<T: bytemuck::Pod>(writer: impl Write, value: &T) {
         write_bytes(writer, bytes_of(value));
     }
 
@@ -1,18 +1,35 @@
-use std::{sync::{Arc, Mutex}, cmp::min, iter, time::Duration};
+use std::{borrow::Cow, cmp::min, sync::{Arc, mpsc}, time::Duration};
 
-use crate::DemoBuilder;
+use crate::{DemoBuilder, time::TimeSource};
+
+use playback::{Clock, Command};
+
+pub(crate) use playback::Playback;
+
+mod mix;
+mod playback;
+mod resample;
+mod ring;
+#[cfg(feature = "ogg")]
+mod stream;
+#[cfg(feature = "wav")]
+mod wav;
+
+/// How many commands can be waiting for the audio thread
+const COMMANDS: usize = 64;
 
 impl DemoBuilder {
     #[cfg(feature = "ogg")]
     pub fn with_ogg_music(mut self, data: &[u8], samples_hint: Option<usize>) -> DemoBuilder {
         let mut cursor = std::io::Cursor::new(data);
         let mut reader = lewton::inside_ogg::OggStreamReader::new(&mut cursor).unwrap();
-        assert_eq!(2, reader.ident_hdr.audio_channels);
+        let channels = reader.ident_hdr.audio_channels as u16;
 
         let mut data = Vec::with_capacity(samples_hint.unwrap_or_default());
         while let Some(mut pck) = reader.read_dec_packet_itl().unwrap() {
             data.append(&mut pck);
         }
+        mix::from_vorbis_order(channels as usize, &mut data);
 
         #[cfg(debug_assertions)]
         println!(
@@ -21,46 +38,279 @@ impl DemoBuilder {
             data.len() * std::mem::size_of::<i16>(),
         );
 
-        self.demo.music = Some(Arc::new(Mutex::new(Music {
-            sample_rate: reader.ident_hdr.audio_sample_rate,
-            data,
-            position: 0,
-            #[cfg(feature = "editor")]
-            paused: false,
-            #[cfg(feature = "editor")]
-            zero: Vec::new(),
-        })));
+        self.demo.music = Some(Music::new(reader.ident_hdr.audio_sample_rate, channels, Samples::Decoded(data)));
+        self
+    }
+
+    /// Like `with_ogg_music`, but decodes the music on a background thread while it plays rather
+    /// than all of it up front, which starts faster and takes far less memory for long tracks.
+    #[cfg(feature = "ogg")]
+    pub fn with_streamed_ogg_music(mut self, data: &'static [u8]) -> DemoBuilder {
+        // Music that can not be decoded is reported the same way as a failing audio stream
+        let errors = mpsc::channel();
+        let stream = stream::Stream::new(data, errors.0.clone()).unwrap();
+        let channels = stream.channels() as u16;
+        let mut music = Music::new(stream.sample_rate(), channels, Samples::Streamed(stream));
+        music.errors = errors;
+        self.demo.music = Some(music);
+        self
+    }
+
+    /// Loads music from a WAV file with 16 or 24-bit integer or 32-bit float samples, such as the
+    /// renders handed over before the music is final. Panics with what is wrong with the file if it
+    /// can not be read.
+    #[cfg(feature = "wav")]
+    pub fn with_wav_music(mut self, data: &[u8]) -> DemoBuilder {
+        let wav = wav::read(data).unwrap_or_else(|err| panic!("Failed to load WAV music: {}", err));
+        self.demo.music = Some(Music::from_pcm(wav.sample_rate, wav.channels, wav.samples));
+        self
+    }
+
+    pub fn with_music(mut self, music: Music) -> DemoBuilder {
+        self.demo.music = Some(music);
         self
     }
 }
 
+/// Where the samples of the music come from.
+enum Samples {
+    /// Every sample, decoded up front
+    Decoded(Vec<i16>),
+    /// Samples decoded a little ahead of playback
+    #[cfg(feature = "ogg")]
+    Streamed(stream::Stream),
+}
+
+impl Samples {
+    /// Up to `len` samples from sample `position` on, fewer at the end or when a stream falls behind.
+    fn get(&mut self, position: usize, len: usize) -> &[i16] {
+        match self {
+            Samples::Decoded(data) => {
+                let start = min(position, data.len());
+                &data[start..min(position + len, data.len())]
+            },
+            #[cfg(feature = "ogg")]
+            Samples::Streamed(stream) => stream.get(position, len),
+        }
+    }
+
+    #[cfg_attr(not(feature = "ogg"), allow(unused_variables))]
+    fn seek(&mut self, position: usize) {
+        match self {
+            Samples::Decoded(_) => (),
+            #[cfg(feature = "ogg")]
+            Samples::Streamed(stream) => stream.seek(position),
+        }
+    }
+}
+
+/// Music played on the audio thread, controlled from the demo without ever making it wait.
 pub struct Music {
     pub(super) sample_rate: u32,
-    pub(super) data: Vec<i16>,
-    pub(super) position: usize,
-    #[cfg(feature = "editor")]
-    pub(super) paused: bool,
+    pub(super) channels: u16,
+    /// The playback, until it is moved to the audio thread
+    playback: Option<Playback>,
+    clock: Arc<Clock>,
+    commands: Arc<ring::RingBuffer<u64>>,
+    paused: bool,
     #[cfg(feature = "editor")]
-    zero: Vec<i16>,
+    rate: f32,
+    /// Errors from the audio stream and the decoder, sent from wherever they report them
+    errors: (mpsc::Sender<String>, mpsc::Receiver<String>),
 }
 
 impl Music {
-    pub fn read<'a>(&mut self, len: usize) -> &[i16] {
-        #[cfg(feature = "editor")]
-        if self.paused {
-            if self.zero.len() < len {
-                self.zero = iter::repeat(0).take(len).collect();
-            }
-            return &self.zero;
+    fn new(sample_rate: u32, channels: u16, samples: Samples) -> Music {
+        let clock = Arc::new(Clock::new(sample_rate, channels as usize));
+        let commands = Arc::new(ring::RingBuffer::new(COMMANDS));
+        Music {
+            sample_rate,
+            channels,
+            playback: Some(Playback::new(samples, channels as usize, clock.clone(), commands.clone())),
+            clock,
+            commands,
+            paused: false,
+            #[cfg(feature = "editor")]
+            rate: 1f32,
+            errors: mpsc::channel(),
         }
+    }
 
-        let data = &self.data[self.position..self.position + len];
-        self.position += min(self.data.len(), len);
-        data
+    /// Music from raw interleaved samples with any number of channels, in the order of WAV files
+    /// when there are more than two. It is mixed to the channels of the audio device when played.
+    pub fn from_pcm(sample_rate: u32, channels: u16, mut samples: Vec<i16>) -> Music {
+        assert!(channels > 0, "Music needs at least one channel");
+        samples.truncate(samples.len() / channels as usize * channels as usize);
+        Music::new(sample_rate, channels, Samples::Decoded(samples))
     }
 
-    #[cfg(feature = "editor")]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes the music. If the audio thread has fallen too far behind to be told, nothing
+    /// changes and [`Music::is_paused`] says so.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused && self.send(Command::Pause(paused)) {
+            self.paused = paused;
+        }
+    }
+
+    /// Moves playback to the frame closest to `position`.
     pub fn seek(&mut self, position: &Duration) {
-        self.position = (position.as_secs_f32() * self.sample_rate as f32) as usize * 2;
+        // Whole nanoseconds, so that the frame is exact however long the music is
+        let frame = (position.as_nanos() * self.sample_rate as u128 + 500_000_000) / 1_000_000_000;
+        let position = frame as usize * self.channels as usize;
+        self.clock.request_seek(position);
+        match &mut self.playback {
+            Some(playback) => playback.follow_seek(),
+            None => (),
+        }
+    }
+
+    #[cfg(feature = "editor")]
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Changes the playback speed, which also changes the pitch, with the music silent at 0. Like
+    /// pausing, nothing changes if the audio thread has fallen too far behind to be told. Panics if
+    /// the rate is negative or not finite.
+    #[cfg(feature = "editor")]
+    pub fn set_rate(&mut self, rate: f32) {
+        assert!(rate.is_finite() && rate >= 0f32, "Invalid playback rate {}", rate);
+        if rate != self.rate && self.send(Command::Rate(rate)) {
+            self.rate = rate;
+        }
+    }
+
+    /// The position of what is being heard right now.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    pub fn time_source(&self) -> AudioTimeSource {
+        AudioTimeSource { clock: self.clock.clone() }
+    }
+
+    /// The last error reported by the audio stream or the decoder of streamed music, after which the
+    /// music is likely to have stopped.
+    pub fn error(&self) -> Option<String> {
+        self.errors.1.try_iter().last()
+    }
+
+    pub(crate) fn error_sender(&self) -> mpsc::Sender<String> {
+        self.errors.0.clone()
+    }
+
+    /// Takes what is needed to play the music, to be moved to the audio thread.
+    pub(crate) fn take_playback(&mut self) -> Option<Playback> {
+        self.playback.take()
+    }
+
+    /// Every sample of the music, which are decoded again if the music is streamed.
+    pub(crate) fn samples(&self) -> Cow<'_, [i16]> {
+        match self.playback.as_ref().expect("The music is already playing").samples() {
+            Samples::Decoded(data) => Cow::Borrowed(data),
+            #[cfg(feature = "ogg")]
+            Samples::Streamed(stream) => Cow::Owned(stream.decode_all()),
+        }
+    }
+
+    /// Carries out the command right away if the music is not playing, or has it carried out by the
+    /// audio thread with the next samples it reads. Returns `false` if the queue of commands is full.
+    fn send(&mut self, command: Command) -> bool {
+        match &mut self.playback {
+            Some(playback) => {
+                playback.apply(command);
+                true
+            },
+            None => self.commands.push(&[command.encode()]) == 1,
+        }
+    }
+}
+
+/// Time taken from the music being played, so that visuals stay in sync with what is heard.
+pub struct AudioTimeSource {
+    clock: Arc<Clock>,
+}
+
+impl TimeSource for AudioTimeSource {
+    fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_queued_once_playing() {
+        let mut music = Music::new(1000, 2, Samples::Decoded(vec![1; 64]));
+
+        // Before playing, commands are carried out right away
+        music.seek(&Duration::from_millis(2));
+        assert_eq!(Duration::from_millis(2), music.elapsed());
+
+        let mut playback = music.take_playback().unwrap();
+        music.set_paused(true);
+        music.seek(&Duration::from_millis(8));
+        assert_eq!(Duration::from_millis(8), music.elapsed());
+        assert_eq!(&[0; 4], playback.read(4));
+
+        music.set_paused(false);
+        assert_eq!(&[1, 1, 1, 1], playback.read(4));
+        assert!(music.elapsed() >= Duration::from_millis(8));
+    }
+
+    #[test]
+    fn commands_that_do_not_fit_are_not_lost() {
+        let mut music = Music::new(1000, 2, Samples::Decoded(vec![1; 64]));
+        let mut playback = music.take_playback().unwrap();
+
+        // Only changes are queued, so asking over and over does not fill the queue
+        for _ in 0..COMMANDS * 2 {
+            music.set_paused(true);
+        }
+        assert_eq!(&[0; 4], playback.read(4));
+        music.set_paused(false);
+        assert_eq!(&[1; 4], playback.read(4));
+
+        // While the queue is full, the music says what state it is actually in
+        for _ in 0..COMMANDS {
+            music.set_paused(true);
+            music.set_paused(false);
+        }
+        music.set_paused(true);
+        assert!(!music.is_paused());
+        playback.read(0);
+        music.set_paused(true);
+        assert!(music.is_paused());
+        assert_eq!(&[0; 4], playback.read(4));
+    }
+
+    #[test]
+    fn seeking_counts_every_channel() {
+        let mut mono = Music::from_pcm(1000, 1, vec![1; 64]);
+        mono.seek(&Duration::from_millis(8));
+        assert_eq!(Duration::from_millis(8), mono.elapsed());
+
+        let mut surround = Music::from_pcm(1000, 6, vec![1; 64 * 6]);
+        surround.seek(&Duration::from_millis(8));
+        assert_eq!(Duration::from_millis(8), surround.elapsed());
+        assert_eq!(&[1; 6], surround.take_playback().unwrap().read(6));
+    }
+
+    #[test]
+    fn seeking_is_exact_far_into_the_music() {
+        let mut music = Music::from_pcm(48000, 2, vec![1; 2]);
+        // Frame 19200007, past where an f32 can tell odd frames apart
+        music.seek(&Duration::from_nanos(400_000_145_833));
+        assert!((music.elapsed().as_secs_f64() - 19200007f64 / 48000f64).abs() < 1e-6);
+
+        // Times between two frames go to the closest one
+        music.seek(&Duration::from_nanos(1_000_015_000));
+        assert!((music.elapsed().as_secs_f64() - 1.000020833f64).abs() < 1e-6);
     }
 }
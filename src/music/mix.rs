@@ -0,0 +1,121 @@
+//! Mixing of music to output devices with more or fewer channels than it has. Channels of more than
+//! two are expected in the order of WAV files: front left, front right, center, low frequency
+//! effects and then the surrounds.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+const FRONT_LEFT: (f32, f32) = (1f32, 0f32);
+const FRONT_RIGHT: (f32, f32) = (0f32, 1f32);
+const CENTER: (f32, f32) = (FRAC_1_SQRT_2, FRAC_1_SQRT_2);
+const LFE: (f32, f32) = (0f32, 0f32);
+const SURROUND_LEFT: (f32, f32) = (FRAC_1_SQRT_2, 0f32);
+const SURROUND_RIGHT: (f32, f32) = (0f32, FRAC_1_SQRT_2);
+/// Split between both surrounds, quieter than the center so that it does not end up in front
+const BACK_CENTER: (f32, f32) = (0.5f32, 0.5f32);
+
+/// How much of each channel goes to the left and right when mixed down to stereo, by channel count.
+/// Channels past the eighth are dropped.
+const LAYOUTS: [&[(f32, f32)]; 8] = [
+    &[(1f32, 1f32)],
+    &[FRONT_LEFT, FRONT_RIGHT],
+    &[FRONT_LEFT, FRONT_RIGHT, CENTER],
+    &[FRONT_LEFT, FRONT_RIGHT, SURROUND_LEFT, SURROUND_RIGHT],
+    &[FRONT_LEFT, FRONT_RIGHT, CENTER, SURROUND_LEFT, SURROUND_RIGHT],
+    &[FRONT_LEFT, FRONT_RIGHT, CENTER, LFE, SURROUND_LEFT, SURROUND_RIGHT],
+    &[FRONT_LEFT, FRONT_RIGHT, CENTER, LFE, BACK_CENTER, SURROUND_LEFT, SURROUND_RIGHT],
+    &[FRONT_LEFT, FRONT_RIGHT, CENTER, LFE, SURROUND_LEFT, SURROUND_RIGHT, SURROUND_LEFT, SURROUND_RIGHT],
+];
+
+/// Appends interleaved frames of `from` channels to `output` as frames of `to` channels. Music is
+/// played as it is when the counts are the same, otherwise it is mixed through stereo, which ends
+/// up on the front speakers of devices with more channels.
+pub(super) fn mix(input: &[i16], from: usize, to: usize, output: &mut Vec<i16>) {
+    if from == to {
+        output.extend_from_slice(input);
+        return;
+    }
+
+    let layout = LAYOUTS[from.min(LAYOUTS.len()) - 1];
+    for frame in input.chunks_exact(from) {
+        let (left, right) = frame
+            .iter()
+            .zip(layout)
+            .fold((0f32, 0f32), |(left, right), (sample, (to_left, to_right))| {
+                (left + *sample as f32 * to_left, right + *sample as f32 * to_right)
+            });
+
+        let clamp = |x: f32| x.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        match to {
+            1 => output.push(clamp((left + right) / 2f32)),
+            _ => {
+                output.extend_from_slice(&[clamp(left), clamp(right)]);
+                output.resize(output.len() + to - 2, 0);
+            },
+        }
+    }
+}
+
+/// Reorders interleaved Vorbis samples to the channel order of WAV files. The orders only differ
+/// when there is a center channel.
+#[cfg(feature = "ogg")]
+pub(super) fn from_vorbis_order(channels: usize, samples: &mut [i16]) {
+    // Which Vorbis channel each WAV channel is
+    let order: &[usize] = match channels {
+        3 => &[0, 2, 1],
+        5 => &[0, 2, 1, 3, 4],
+        6 => &[0, 2, 1, 5, 3, 4],
+        7 => &[0, 2, 1, 6, 5, 3, 4],
+        8 => &[0, 2, 1, 7, 5, 6, 3, 4],
+        _ => return,
+    };
+
+    let mut vorbis = [0i16; 8];
+    for frame in samples.chunks_exact_mut(channels) {
+        vorbis[..channels].copy_from_slice(frame);
+        for (sample, channel) in frame.iter_mut().zip(order) {
+            *sample = vorbis[*channel];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mixed(input: &[i16], from: usize, to: usize) -> Vec<i16> {
+        let mut output = Vec::new();
+        mix(input, from, to, &mut output);
+        output
+    }
+
+    #[test]
+    fn channels_are_mixed_through_stereo() {
+        assert_eq!(vec![1, 1, 2, 2], mixed(&[1, 2], 1, 2));
+        assert_eq!(vec![150], mixed(&[100, 200], 2, 1));
+        assert_eq!(vec![1, 2, 0, 0, 0, 0], mixed(&[1, 2], 2, 6));
+
+        // Center split between both sides, low frequency effects dropped
+        assert_eq!(vec![1707, 2707], mixed(&[1000, 2000, 1000, 5000, 0, 0], 6, 2));
+        assert_eq!(vec![i16::MAX, i16::MIN], mixed(&[30000, -30000, 30000, -30000], 4, 2));
+
+        // The back center of 6.1 goes to both sides, more quietly than the front center
+        assert_eq!(vec![500, 500], mixed(&[0, 0, 0, 0, 1000, 0, 0], 7, 2));
+        assert_eq!(vec![707, 707], mixed(&[0, 0, 1000, 0, 0, 0, 0], 7, 2));
+
+        // The same number of channels is played as it is
+        assert_eq!(vec![1, 2, 3], mixed(&[1, 2, 3], 3, 3));
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn vorbis_channels_are_reordered() {
+        // Front left, center, front right, rear left, rear right and low frequency effects
+        let mut samples = [1, 3, 2, 5, 6, 4, 1, 3, 2, 5, 6, 4];
+        from_vorbis_order(6, &mut samples);
+        assert_eq!([1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 6], samples);
+
+        let mut stereo = [1, 2];
+        from_vorbis_order(2, &mut stereo);
+        assert_eq!([1, 2], stereo);
+    }
+}
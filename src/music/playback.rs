@@ -0,0 +1,461 @@
+//! The part of the music that is moved to the audio thread once it starts playing. It is only told
+//! what to do through a queue of commands and the seek kept in its clock, and only reports back
+//! through atomics, so that the audio thread never has to wait on the demo.
+
+#[cfg(feature = "editor")]
+use std::cmp::min;
+use std::{
+    sync::{Arc, atomic::{fence, AtomicU32, AtomicU64, AtomicUsize, Ordering}},
+    time::{Duration, Instant},
+};
+
+use super::{mix, resample::Resampler, ring::RingBuffer, Samples};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Command {
+    Pause(bool),
+    #[cfg(feature = "editor")]
+    Rate(f32),
+}
+
+impl Command {
+    const PAUSE: u64 = 0;
+    #[cfg(feature = "editor")]
+    const RATE: u64 = 1;
+
+    /// Packs the command into a single value, the kind in the top byte and its argument below.
+    pub fn encode(self) -> u64 {
+        match self {
+            Command::Pause(paused) => Command::PAUSE << 56 | paused as u64,
+            #[cfg(feature = "editor")]
+            Command::Rate(rate) => Command::RATE << 56 | rate.to_bits() as u64,
+        }
+    }
+
+    pub fn decode(value: u64) -> Command {
+        let argument = value & ((1 << 56) - 1);
+        match value >> 56 {
+            #[cfg(feature = "editor")]
+            Command::RATE => Command::Rate(f32::from_bits(argument as u32)),
+            _ => Command::Pause(argument != 0),
+        }
+    }
+}
+
+/// What the audio thread last did, as seen from other threads.
+#[derive(Debug, Clone, Copy)]
+struct Read {
+    /// Nanoseconds after the clock was created that samples were last handed to the audio device,
+    /// or `None` while nothing is playing
+    at: Option<u64>,
+    /// Where the samples handed out started, and how many there were
+    position: usize,
+    len: usize,
+    rate: f32,
+    latency: Duration,
+    /// How many seeks had been carried out
+    seeks: usize,
+}
+
+/// The playback position, written by whoever holds the [`Playback`] and read from anywhere.
+pub(super) struct Clock {
+    sample_rate: u32,
+    channels: usize,
+    base: Instant,
+    /// Odd while a read is being written, so that readers can tell they saw it half written
+    sequence: AtomicUsize,
+    at: AtomicU64,
+    position: AtomicUsize,
+    len: AtomicUsize,
+    rate: AtomicU32,
+    latency: AtomicU64,
+    seeks: AtomicUsize,
+    /// How many seeks have been asked for, and where the last one goes. Only the last one is carried
+    /// out, so seeking never has to wait for room in the queue of commands
+    requested: AtomicUsize,
+    seek_to: AtomicUsize,
+}
+
+impl Clock {
+    pub fn new(sample_rate: u32, channels: usize) -> Clock {
+        Clock {
+            sample_rate,
+            channels,
+            base: Instant::now(),
+            sequence: AtomicUsize::new(0),
+            at: AtomicU64::new(u64::MAX),
+            position: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            rate: AtomicU32::new(1f32.to_bits()),
+            latency: AtomicU64::new(0),
+            seeks: AtomicUsize::new(0),
+            requested: AtomicUsize::new(0),
+            seek_to: AtomicUsize::new(0),
+        }
+    }
+
+    /// Asks the playback to seek with the next samples it reads. Time jumps right away, before the
+    /// seek is carried out.
+    pub fn request_seek(&self, position: usize) {
+        self.seek_to.store(position, Ordering::Relaxed);
+        self.requested.fetch_add(1, Ordering::Release);
+    }
+
+    /// The position of what is being heard right now. Between reads the position is moved along with
+    /// the wall clock, but never past the end of the samples read so far.
+    pub fn elapsed(&self) -> Duration {
+        let seconds = |samples: usize| samples as f64 / self.channels as f64 / self.sample_rate as f64;
+
+        let requested = self.requested.load(Ordering::Acquire);
+        let read = self.read();
+        if read.seeks != requested {
+            return Duration::from_secs_f64(seconds(self.seek_to.load(Ordering::Relaxed)));
+        }
+
+        let position = match read.at {
+            Some(at) => {
+                let since = (self.base.elapsed().as_nanos() as u64).saturating_sub(at) as f64 / 1e9;
+                let since = since.min(seconds(read.len));
+                seconds(read.position) + (since - read.latency.as_secs_f64()) * read.rate as f64
+            },
+            None => seconds(read.position),
+        };
+        Duration::from_secs_f64(position.max(0f64))
+    }
+
+    fn read(&self) -> Read {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            let read = Read {
+                at: match self.at.load(Ordering::Relaxed) {
+                    u64::MAX => None,
+                    at => Some(at),
+                },
+                position: self.position.load(Ordering::Relaxed),
+                len: self.len.load(Ordering::Relaxed),
+                rate: f32::from_bits(self.rate.load(Ordering::Relaxed)),
+                latency: Duration::from_nanos(self.latency.load(Ordering::Relaxed)),
+                seeks: self.seeks.load(Ordering::Relaxed),
+            };
+            fence(Ordering::Acquire);
+            if sequence & 1 == 0 && self.sequence.load(Ordering::Relaxed) == sequence {
+                return read;
+            }
+        }
+    }
+
+    fn write(&self, read: Read) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.at.store(read.at.unwrap_or(u64::MAX), Ordering::Relaxed);
+        self.position.store(read.position, Ordering::Relaxed);
+        self.len.store(read.len, Ordering::Relaxed);
+        self.rate.store(read.rate.to_bits(), Ordering::Relaxed);
+        self.latency.store(read.latency.as_nanos() as u64, Ordering::Relaxed);
+        self.seeks.store(read.seeks, Ordering::Relaxed);
+
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+}
+
+pub(crate) struct Playback {
+    samples: Samples,
+    /// How many channels the music has, and how many the audio device plays
+    channels: usize,
+    output_channels: usize,
+    /// Converts the music to the sample rate of the audio device, when it can not play the music's
+    resampler: Option<Resampler>,
+    position: usize,
+    paused: bool,
+    /// How long it takes for samples handed to the audio device to be heard
+    latency: Duration,
+    /// Playback speed, which also changes the pitch
+    #[cfg(feature = "editor")]
+    rate: f32,
+    /// How far playback is between the frame at `position` and the next one
+    #[cfg(feature = "editor")]
+    fraction: f32,
+    /// The samples of the music read last, and what they were mixed to for the audio device
+    source: Vec<i16>,
+    buffer: Vec<i16>,
+    seeks: usize,
+    clock: Arc<Clock>,
+    commands: Arc<RingBuffer<u64>>,
+}
+
+impl Playback {
+    pub(super) fn new(samples: Samples, channels: usize, clock: Arc<Clock>, commands: Arc<RingBuffer<u64>>) -> Playback {
+        Playback {
+            samples,
+            channels,
+            output_channels: channels,
+            resampler: None,
+            position: 0,
+            paused: false,
+            latency: Duration::ZERO,
+            #[cfg(feature = "editor")]
+            rate: 1f32,
+            #[cfg(feature = "editor")]
+            fraction: 0f32,
+            source: Vec::new(),
+            buffer: Vec::new(),
+            seeks: 0,
+            clock,
+            commands,
+        }
+    }
+
+    pub(super) fn samples(&self) -> &Samples {
+        &self.samples
+    }
+
+    /// Sets how many channels the samples read are mixed to, which is the number of channels of the
+    /// music until then.
+    pub fn set_output_channels(&mut self, channels: usize) {
+        self.output_channels = channels;
+    }
+
+    /// Sets the sample rate the samples read are converted to, which is that of the music until then.
+    pub fn set_output_rate(&mut self, sample_rate: u32) {
+        self.resampler = if sample_rate == self.clock.sample_rate {
+            None
+        } else {
+            Some(Resampler::new(self.channels, self.clock.sample_rate, sample_rate))
+        };
+    }
+
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = latency;
+    }
+
+    /// The next `len` samples to play, at the output sample rate and mixed to the output channels.
+    /// They are silent past the end of the music, while paused or at a rate of 0.
+    pub fn read(&mut self, len: usize) -> &[i16] {
+        let mut command = [0u64];
+        while self.commands.pop(&mut command) == 1 {
+            self.apply(Command::decode(command[0]));
+        }
+        self.follow_seek();
+
+        self.buffer.clear();
+        #[cfg(feature = "editor")]
+        let stopped = self.paused || self.rate == 0f32;
+        #[cfg(not(feature = "editor"))]
+        let stopped = self.paused;
+        if stopped {
+            self.buffer.resize(len, 0);
+            return &self.buffer;
+        }
+
+        // How many frames of the music to read, and how many of them are played by this read
+        let frames = len / self.output_channels;
+        let (input, played) = match &self.resampler {
+            Some(resampler) => (resampler.needed(frames), resampler.input_frames(frames)),
+            None => (frames, frames),
+        };
+        self.publish(Some(played * self.channels));
+
+        self.source.clear();
+        #[cfg(feature = "editor")]
+        let resampled = self.rate != 1f32;
+        #[cfg(not(feature = "editor"))]
+        let resampled = false;
+        if resampled {
+            #[cfg(feature = "editor")]
+            self.read_resampled(input);
+        } else {
+            let samples = self.samples.get(self.position, input * self.channels);
+            self.position += samples.len();
+            self.source.extend_from_slice(samples);
+        }
+
+        let source = match &mut self.resampler {
+            Some(resampler) => resampler.process(&self.source, frames),
+            None => {
+                self.source.resize(frames * self.channels, 0);
+                &self.source
+            },
+        };
+        mix::mix(source, self.channels, self.output_channels, &mut self.buffer);
+        self.buffer.resize(len, 0);
+        &self.buffer
+    }
+
+    pub(super) fn apply(&mut self, command: Command) {
+        match command {
+            Command::Pause(paused) => self.paused = paused,
+            #[cfg(feature = "editor")]
+            Command::Rate(rate) => self.rate = rate,
+        }
+        self.publish(None);
+    }
+
+    /// Carries out the last seek asked for through the clock, unless it already has been.
+    pub(super) fn follow_seek(&mut self) {
+        let requested = self.clock.requested.load(Ordering::Acquire);
+        if requested == self.seeks {
+            return;
+        }
+
+        let position = self.clock.seek_to.load(Ordering::Relaxed);
+        self.position = position;
+        #[cfg(feature = "editor")]
+        {
+            self.fraction = 0f32;
+        }
+        self.samples.seek(position);
+        match &mut self.resampler {
+            Some(resampler) => resampler.reset(),
+            None => (),
+        }
+        self.seeks = requested;
+        self.publish(None);
+    }
+
+    /// Lets other threads know where playback is, and how many samples from there are being played
+    /// if any.
+    fn publish(&self, len: Option<usize>) {
+        #[cfg(feature = "editor")]
+        let rate = self.rate;
+        #[cfg(not(feature = "editor"))]
+        let rate = 1f32;
+
+        // Frames given to the resampler ahead of what it has converted have not been played yet
+        let ahead = self.resampler.as_ref().map_or(0, |x| x.ahead());
+
+        self.clock.write(Read {
+            at: len.map(|_| self.clock.base.elapsed().as_nanos() as u64),
+            position: self.position.saturating_sub(ahead * self.channels),
+            len: len.unwrap_or_default(),
+            rate,
+            latency: self.latency,
+            seeks: self.seeks,
+        });
+    }
+
+    /// Reads `frames` frames played back at `rate`, interpolating linearly between them.
+    #[cfg(feature = "editor")]
+    fn read_resampled(&mut self, frames: usize) {
+        let channels = self.channels;
+        let advanced = self.fraction + frames as f32 * self.rate;
+
+        // Every frame passed over, along with the one after the last for interpolating towards
+        let data = self.samples.get(self.position, (advanced as usize + 2) * channels);
+        let sample = |index: usize| data.get(index).copied().unwrap_or(0) as f32;
+
+        for frame in 0..frames {
+            let offset = self.fraction + frame as f32 * self.rate;
+            let index = offset as usize * channels;
+            let t = offset.fract();
+            for channel in 0..channels {
+                let a = sample(index + channel);
+                let b = sample(index + channel + channels);
+                self.source.push((a + (b - a) * t) as i16);
+            }
+        }
+
+        self.position += min(data.len(), advanced as usize * channels);
+        self.fraction = advanced.fract();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playback(data: Vec<i16>, sample_rate: u32) -> Playback {
+        Playback::new(
+            Samples::Decoded(data),
+            2,
+            Arc::new(Clock::new(sample_rate, 2)),
+            Arc::new(RingBuffer::new(4)),
+        )
+    }
+
+    #[test]
+    fn commands_survive_encoding() {
+        for command in [Command::Pause(true), Command::Pause(false)] {
+            assert_eq!(command, Command::decode(command.encode()));
+        }
+    }
+
+    #[cfg(feature = "editor")]
+    #[test]
+    fn music_is_resampled_to_rate() {
+        // Stereo frames with the same ramp on both channels
+        let mut playback = playback(vec![0, 0, 10, 10, 20, 20, 30, 30, 40, 40], 4);
+
+        playback.apply(Command::Rate(0.5f32));
+        assert_eq!(&[0, 0, 5, 5, 10, 10, 15, 15], playback.read(8));
+        assert_eq!(4, playback.position);
+
+        playback.apply(Command::Rate(2f32));
+        assert_eq!(&[20, 20, 40, 40], playback.read(4));
+
+        // Reading past the end is silent, and so is a rate of 0
+        assert_eq!(&[0, 0, 0, 0], playback.read(4));
+        playback.clock.request_seek(2);
+        playback.apply(Command::Rate(0f32));
+        assert_eq!(&[0, 0, 0, 0], playback.read(4));
+    }
+
+    #[test]
+    fn elapsed_follows_samples_read() {
+        let mut playback = playback(vec![0; 64], 1000);
+        let clock = playback.clock.clone();
+
+        // Seeks move time right away, before the audio thread has got to them
+        clock.request_seek(8);
+        assert_eq!(Duration::from_millis(4), clock.elapsed());
+
+        // Two frames take 2 ms to play, time does not move beyond them before the next read
+        playback.read(4);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(6), clock.elapsed());
+
+        playback.set_latency(Duration::from_millis(1));
+        playback.read(0);
+        assert_eq!(Duration::from_millis(5), clock.elapsed());
+    }
+
+    #[test]
+    fn only_the_last_seek_is_carried_out() {
+        let mut playback = playback(vec![0, 1, 2, 3, 4, 5, 6, 7], 1000);
+        let clock = playback.clock.clone();
+
+        // More seeks than would fit in the queue of commands, none of them are lost
+        for position in 0..100 {
+            clock.request_seek(position % 4 * 2);
+        }
+        assert_eq!(Duration::from_millis(3), clock.elapsed());
+        assert_eq!(&[6, 7], playback.read(2));
+    }
+
+    #[test]
+    fn music_is_mixed_to_output_channels() {
+        let mut playback = playback(vec![1, 2, 3, 4], 1000);
+        let clock = playback.clock.clone();
+
+        // Two frames on a mono device take as long to play as on a stereo one
+        playback.set_output_channels(1);
+        assert_eq!(&[2, 4], playback.read(2));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(2), clock.elapsed());
+    }
+
+    #[test]
+    fn elapsed_is_exact_when_converting_sample_rate() {
+        let mut playback = playback(vec![0; 2 * 44100], 44100);
+        let clock = playback.clock.clone();
+        playback.set_output_rate(48000);
+
+        // However it is read, 480 frames at 48 kHz are 441 frames of the music
+        clock.request_seek(2 * 4410);
+        for len in [2 * 100, 2 * 3, 2 * 257, 2 * 120, 0] {
+            assert_eq!(len, playback.read(len).len());
+        }
+        assert_eq!(Duration::from_millis(110), clock.elapsed());
+    }
+}
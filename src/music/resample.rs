@@ -0,0 +1,215 @@
+//! Conversion of music to the sample rate of the output device, for devices that can not play it at
+//! its own rate. Output frames are taken from the music at exact fractions of its frames, so that
+//! however the reads are split up the same output frame always plays the same moment of the music.
+
+use std::f64::consts::PI;
+
+/// How many input frames on each side of an output frame are filtered, when not downsampling
+const HALF_TAPS: usize = 32;
+/// Where the filter starts rolling off, relative to the lower of the two Nyquist frequencies
+const CUTOFF: f64 = 0.95;
+/// Shape of the Kaiser window, trading the width of the roll-off for rejection of aliases
+const BETA: f64 = 8.6;
+
+/// A polyphase windowed sinc filter. Output frame `n` after a reset is input frame `n * down / up`,
+/// with `up` different filters for the fractions in between.
+pub(super) struct Resampler {
+    channels: usize,
+    up: usize,
+    down: usize,
+    /// Input frames on each side of an output frame that are filtered
+    half: usize,
+    /// The `2 * half` coefficients of each of the `up` filters
+    filter: Vec<f32>,
+    /// Interleaved input frames still needed, and the output frame's position among them
+    frames: Vec<f32>,
+    index: usize,
+    phase: usize,
+    /// The frames written by the last call to `process`
+    output: Vec<i16>,
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+/// The zeroth order modified Bessel function of the first kind, which the Kaiser window is made of.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1f64;
+    let mut term = 1f64;
+    for k in 1..32 {
+        term *= (x / 2f64 / k as f64).powi(2);
+        sum += term;
+    }
+    sum
+}
+
+impl Resampler {
+    pub fn new(channels: usize, from: u32, to: u32) -> Resampler {
+        let divisor = gcd(from as usize, to as usize);
+        let (up, down) = (to as usize / divisor, from as usize / divisor);
+
+        // When downsampling the filter has to cut off below the Nyquist frequency of the output, and
+        // gets wider to do so just as sharply
+        let ratio = (up as f64 / down as f64).min(1f64);
+        let cutoff = CUTOFF * ratio;
+        let half = (HALF_TAPS as f64 / ratio).ceil() as usize;
+
+        let mut filter = Vec::with_capacity(up * half * 2);
+        for phase in 0..up {
+            let start = filter.len();
+            for tap in 0..half * 2 {
+                // How far the input frame is from the output frame
+                let distance = phase as f64 / up as f64 + (half - 1) as f64 - tap as f64;
+                let x = PI * distance * cutoff;
+                let sinc = if x == 0f64 { 1f64 } else { x.sin() / x };
+                let window = bessel_i0(BETA * (1f64 - (distance / half as f64).powi(2)).max(0f64).sqrt()) / bessel_i0(BETA);
+                filter.push((sinc * window) as f32);
+            }
+
+            // Every filter lets through the same level, so that there is no ripple between them
+            let sum = filter[start..].iter().sum::<f32>();
+            filter[start..].iter_mut().for_each(|x| *x /= sum);
+        }
+
+        let mut resampler = Resampler {
+            channels,
+            up,
+            down,
+            half,
+            filter,
+            frames: Vec::new(),
+            index: 0,
+            phase: 0,
+            output: Vec::new(),
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Starts over with silence before the next input frame, which the next output frame is exactly.
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.frames.resize((self.half - 1) * self.channels, 0f32);
+        self.index = self.half - 1;
+        self.phase = 0;
+    }
+
+    /// How many input frames `output` output frames are taken from over time.
+    pub fn input_frames(&self, output: usize) -> usize {
+        output * self.down / self.up
+    }
+
+    /// How many more input frames are needed to write `output` frames.
+    pub fn needed(&self, output: usize) -> usize {
+        if output == 0 {
+            return 0;
+        }
+        let last = self.index + (self.phase + (output - 1) * self.down) / self.up;
+        (last + self.half + 1).saturating_sub(self.frames.len() / self.channels)
+    }
+
+    /// How many of the input frames given are ahead of the next output frame.
+    pub fn ahead(&self) -> usize {
+        self.frames.len() / self.channels - self.index
+    }
+
+    /// Takes in interleaved input frames, and writes up to `output` frames from what has been taken
+    /// in so far. Fewer are written if there are not enough input frames for them.
+    pub fn process(&mut self, input: &[i16], output: usize) -> &[i16] {
+        let channels = self.channels;
+        let taps = self.half * 2;
+        self.frames.extend(input.iter().map(|x| *x as f32));
+        self.output.clear();
+
+        for _ in 0..output {
+            if self.index + self.half >= self.frames.len() / channels {
+                break;
+            }
+
+            let filter = &self.filter[self.phase * taps..(self.phase + 1) * taps];
+            let frames = &self.frames[(self.index + 1 - self.half) * channels..];
+            for channel in 0..channels {
+                let sample = filter
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, coefficient)| coefficient * frames[tap * channels + channel])
+                    .sum::<f32>();
+                self.output.push(sample.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            }
+
+            self.phase += self.down;
+            self.index += self.phase / self.up;
+            self.phase %= self.up;
+        }
+
+        // Only the frames before the next output frame that it is filtered with are kept
+        let done = self.index + 1 - self.half;
+        self.frames.drain(..done * channels);
+        self.index -= done;
+
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: u32, frame: f64) -> f64 {
+        (2f64 * PI * frequency * frame / sample_rate as f64).sin() * 10000f64
+    }
+
+    #[test]
+    fn sine_keeps_its_shape_and_phase() {
+        let mut resampler = Resampler::new(1, 44100, 48000);
+        let input = (0..44100).map(|x| sine(1000f64, 44100, x as f64) as i16).collect::<Vec<_>>();
+
+        // Fed in uneven reads, the way an audio device asks for them
+        let mut output = Vec::new();
+        let mut read = 0;
+        for frames in [1, 512, 7, 1024, 333].iter().cycle().take(100) {
+            let needed = resampler.needed(*frames);
+            output.extend_from_slice(resampler.process(&input[read..read + needed], *frames));
+            read += needed;
+            assert_eq!(read - resampler.ahead(), resampler.input_frames(output.len()));
+        }
+
+        // Past the silence before the start, every frame is where it would be if sampled at 48 kHz
+        for (frame, sample) in output.iter().enumerate().skip(100) {
+            assert!((sine(1000f64, 48000, frame as f64) - *sample as f64).abs() < 5f64, "{} at {}", sample, frame);
+        }
+    }
+
+    #[test]
+    fn frequencies_above_the_output_are_removed() {
+        let mut resampler = Resampler::new(2, 48000, 22050);
+        let input = (0..48000)
+            .flat_map(|x| [sine(500f64, 48000, x as f64) as i16, sine(15000f64, 48000, x as f64) as i16])
+            .collect::<Vec<_>>();
+
+        let needed = resampler.needed(20000);
+        let output = resampler.process(&input[..needed * 2], 20000);
+        assert_eq!(40000, output.len());
+
+        let peak = |channel: usize| output.iter().skip(2000 + channel).step_by(2).map(|x| x.abs()).max().unwrap();
+        assert!(peak(0) > 9900);
+        assert!(peak(1) < 10);
+    }
+
+    #[test]
+    fn reset_starts_at_the_next_frame() {
+        let mut resampler = Resampler::new(1, 32000, 48000);
+        resampler.process(&[1000; 256], 100);
+        assert!(resampler.ahead() > 0);
+
+        resampler.reset();
+        assert_eq!(0, resampler.ahead());
+        let needed = resampler.needed(300);
+        assert_eq!(300, resampler.process(&vec![1000; needed], 300).len());
+        assert_eq!(200, needed - resampler.ahead());
+    }
+}
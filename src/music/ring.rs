@@ -0,0 +1,140 @@
+//! A fixed size queue that one thread writes to while another reads from it, without either of them
+//! ever waiting on the other. Used for samples and for commands sent to the audio thread.
+
+use std::{cmp::min, sync::atomic::{AtomicI16, AtomicU64, AtomicUsize, Ordering}};
+
+/// Values that can be queued, each kept in an atomic of its own size.
+pub(super) trait Element: Copy {
+    type Atomic;
+
+    fn new(value: Self) -> Self::Atomic;
+    fn load(atomic: &Self::Atomic) -> Self;
+    fn store(atomic: &Self::Atomic, value: Self);
+}
+
+impl Element for i16 {
+    type Atomic = AtomicI16;
+
+    fn new(value: i16) -> AtomicI16 {
+        AtomicI16::new(value)
+    }
+
+    fn load(atomic: &AtomicI16) -> i16 {
+        atomic.load(Ordering::Relaxed)
+    }
+
+    fn store(atomic: &AtomicI16, value: i16) {
+        atomic.store(value, Ordering::Relaxed)
+    }
+}
+
+impl Element for u64 {
+    type Atomic = AtomicU64;
+
+    fn new(value: u64) -> AtomicU64 {
+        AtomicU64::new(value)
+    }
+
+    fn load(atomic: &AtomicU64) -> u64 {
+        atomic.load(Ordering::Relaxed)
+    }
+
+    fn store(atomic: &AtomicU64, value: u64) {
+        atomic.store(value, Ordering::Relaxed)
+    }
+}
+
+pub(super) struct RingBuffer<T: Element> {
+    values: Box<[T::Atomic]>,
+    /// Total number of values written so far, only changed by the writer
+    written: AtomicUsize,
+    /// Total number of values read so far, only changed by the reader
+    read: AtomicUsize,
+}
+
+impl<T: Element + Default> RingBuffer<T> {
+    pub fn new(capacity: usize) -> RingBuffer<T> {
+        RingBuffer {
+            values: (0..capacity).map(|_| T::new(T::default())).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Writes as many of `values` as there is room for, returning how many that was.
+    pub fn push(&self, values: &[T]) -> usize {
+        let written = self.written.load(Ordering::Relaxed);
+        let free = self.capacity() - (written - self.read.load(Ordering::Acquire));
+        let count = min(values.len(), free);
+
+        for (i, value) in values[..count].iter().enumerate() {
+            T::store(&self.values[(written + i) % self.capacity()], *value);
+        }
+        self.written.store(written + count, Ordering::Release);
+        count
+    }
+
+    /// Reads as many values as there are available into `values`, returning how many that was.
+    pub fn pop(&self, values: &mut [T]) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let count = min(values.len(), self.written() - read);
+
+        for (i, value) in values[..count].iter_mut().enumerate() {
+            *value = T::load(&self.values[(read + i) % self.capacity()]);
+        }
+        self.read.store(read + count, Ordering::Release);
+        count
+    }
+
+    /// Drops up to `count` values without reading them, returning how many were dropped.
+    #[cfg(feature = "ogg")]
+    pub fn skip(&self, count: usize) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let count = min(count, self.written() - read);
+        self.read.store(read + count, Ordering::Release);
+        count
+    }
+
+    /// Drops every value written before the total written reached `written`.
+    #[cfg(feature = "ogg")]
+    pub fn skip_to(&self, written: usize) {
+        let read = self.read.load(Ordering::Relaxed);
+        if written > read {
+            self.read.store(written, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ogg"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_wrap_around_the_ring() {
+        let ring = RingBuffer::<i16>::new(4);
+        let mut samples = [0i16; 4];
+
+        assert_eq!(3, ring.push(&[1, 2, 3]));
+        assert_eq!(2, ring.pop(&mut samples[..2]));
+        assert_eq!([1, 2], samples[..2]);
+
+        // Only the free room is written to, wrapping past the end
+        assert_eq!(3, ring.push(&[4, 5, 6, 7]));
+        assert_eq!(4, ring.pop(&mut samples));
+        assert_eq!([3, 4, 5, 6], samples);
+        assert_eq!(0, ring.pop(&mut samples));
+
+        assert_eq!(2, ring.push(&[8, 9]));
+        assert_eq!(1, ring.skip(1));
+        ring.skip_to(ring.written());
+        assert_eq!(0, ring.pop(&mut samples));
+    }
+}
@@ -0,0 +1,354 @@
+//! Ogg Vorbis music decoded on a thread of its own while it plays, staying a little ahead of the
+//! playhead, so that long tracks neither hold up startup nor have to be kept in memory decoded.
+
+use std::{
+    io::Cursor,
+    sync::{Arc, mpsc, atomic::{AtomicBool, AtomicUsize, Ordering}},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use lewton::{inside_ogg::OggStreamReader, VorbisError};
+
+use super::{mix, ring::RingBuffer};
+
+/// How long the decoder sleeps when it is ahead of playback or has reached the end
+const IDLE: Duration = Duration::from_millis(5);
+
+/// What the player and the decoder thread share, without locking.
+struct Shared {
+    ring: RingBuffer<i16>,
+    /// The sample to continue decoding from once `requested` changes
+    seek_to: AtomicUsize,
+    /// How many seeks have been asked of the decoder, and how many it has carried out
+    requested: AtomicUsize,
+    handled: AtomicUsize,
+    /// How many samples had been written to the ring when the last seek was carried out
+    seeked_at: AtomicUsize,
+    stop: AtomicBool,
+}
+
+pub(super) struct Stream {
+    data: &'static [u8],
+    sample_rate: u32,
+    channels: usize,
+    shared: Arc<Shared>,
+    decoder: Option<JoinHandle<()>>,
+    errors: mpsc::Sender<String>,
+    /// Samples taken from the ring but not played yet, the first of which is sample `start`
+    pending: Vec<i16>,
+    start: usize,
+    requested: usize,
+    /// Whether samples are being held back until the decoder has carried out the last seek
+    seeking: bool,
+}
+
+impl Stream {
+    /// Starts decoding the music, reporting anything that can not be decoded to `errors`.
+    pub fn new(data: &'static [u8], errors: mpsc::Sender<String>) -> Result<Stream, VorbisError> {
+        let mut decoder = Decoder::new(data, errors.clone())?;
+        let sample_rate = decoder.reader.ident_hdr.audio_sample_rate;
+        let channels = decoder.reader.ident_hdr.audio_channels as usize;
+
+        // Buffer a second of music, half of which is decoded right away so that playback can start
+        let shared = Arc::new(Shared {
+            ring: RingBuffer::new(sample_rate as usize * channels),
+            seek_to: AtomicUsize::new(0),
+            requested: AtomicUsize::new(0),
+            handled: AtomicUsize::new(0),
+            seeked_at: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+        });
+        while shared.ring.written() < shared.ring.capacity() / 2 && decoder.fill(&shared.ring) {}
+
+        Ok(Stream {
+            data,
+            sample_rate,
+            channels,
+            decoder: Some(thread::spawn({
+                let shared = shared.clone();
+                move || decoder.run(&shared)
+            })),
+            shared,
+            errors,
+            pending: Vec::new(),
+            start: 0,
+            requested: 0,
+            seeking: false,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Up to `len` samples from sample `position` on, which must not be before the position asked
+    /// for last. Fewer are returned when the decoder has fallen behind or the music has ended.
+    pub fn get(&mut self, position: usize, len: usize) -> &[i16] {
+        if self.seeking {
+            if self.shared.handled.load(Ordering::Acquire) != self.requested {
+                return &[];
+            }
+            self.shared.ring.skip_to(self.shared.seeked_at.load(Ordering::Relaxed));
+            self.seeking = false;
+        }
+
+        // Drop what has been played, which may be more than was handed out if the decoder fell behind
+        let played = position.saturating_sub(self.start);
+        let dropped = played.min(self.pending.len());
+        self.pending.drain(..dropped);
+        self.start += dropped + self.shared.ring.skip(played - dropped);
+        if self.start != position {
+            return &[];
+        }
+
+        let pending = self.pending.len();
+        if pending < len {
+            self.pending.resize(len, 0);
+            let popped = self.shared.ring.pop(&mut self.pending[pending..]);
+            self.pending.truncate(pending + popped);
+        }
+
+        // Only whole frames are handed out, so that channels are never swapped
+        let available = self.pending.len().min(len) / self.channels * self.channels;
+        &self.pending[..available]
+    }
+
+    /// Moves playback to sample `position`. Nothing is returned by `get` until the decoder has
+    /// caught up, which takes no longer than decoding a page.
+    pub fn seek(&mut self, position: usize) {
+        self.requested += 1;
+        self.shared.seek_to.store(position, Ordering::Relaxed);
+        self.shared.requested.store(self.requested, Ordering::Release);
+
+        self.pending.clear();
+        self.start = position;
+        self.seeking = true;
+    }
+
+    /// Every sample of the music, decoded separately from playback.
+    pub fn decode_all(&self) -> Vec<i16> {
+        let mut decoder = Decoder::new(self.data, self.errors.clone()).unwrap();
+        let mut samples = Vec::new();
+        while let Some(mut packet) = decoder.next_packet() {
+            samples.append(&mut packet);
+        }
+        samples
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        match self.decoder.take() {
+            Some(decoder) => decoder.join().unwrap(),
+            None => (),
+        }
+    }
+}
+
+struct Decoder {
+    data: &'static [u8],
+    reader: OggStreamReader<Cursor<&'static [u8]>>,
+    /// The last decoded packet, and how much of it has been written to the ring
+    packet: Vec<i16>,
+    offset: usize,
+    errors: mpsc::Sender<String>,
+    /// Whether decoding stopped at a packet that could not be decoded, until the next seek
+    failed: bool,
+}
+
+impl Decoder {
+    fn new(data: &'static [u8], errors: mpsc::Sender<String>) -> Result<Decoder, VorbisError> {
+        Ok(Decoder {
+            data,
+            reader: OggStreamReader::new(Cursor::new(data))?,
+            packet: Vec::new(),
+            offset: 0,
+            errors,
+            failed: false,
+        })
+    }
+
+    fn run(mut self, shared: &Shared) {
+        let mut handled = 0;
+        while !shared.stop.load(Ordering::Relaxed) {
+            let requested = shared.requested.load(Ordering::Acquire);
+            if requested != handled {
+                self.seek(shared.seek_to.load(Ordering::Relaxed));
+                shared.seeked_at.store(shared.ring.written(), Ordering::Relaxed);
+                shared.handled.store(requested, Ordering::Release);
+                handled = requested;
+            }
+
+            if !self.fill(&shared.ring) {
+                thread::sleep(IDLE);
+            }
+        }
+    }
+
+    /// Writes decoded samples to the ring, returning false if there is no room left for them or
+    /// the music has ended.
+    fn fill(&mut self, ring: &RingBuffer<i16>) -> bool {
+        if self.offset == self.packet.len() {
+            match self.next_packet() {
+                Some(packet) => {
+                    self.packet = packet;
+                    self.offset = 0;
+                },
+                None => return false,
+            }
+        }
+
+        self.offset += ring.push(&self.packet[self.offset..]);
+        self.offset == self.packet.len()
+    }
+
+    fn next_packet(&mut self) -> Option<Vec<i16>> {
+        if self.failed {
+            return None;
+        }
+
+        match self.reader.read_dec_packet_itl() {
+            Ok(Some(mut packet)) => {
+                mix::from_vorbis_order(self.reader.ident_hdr.audio_channels as usize, &mut packet);
+                Some(packet)
+            },
+            Ok(None) => None,
+            Err(err) => {
+                // Picked up by the demo on its next frame
+                self.errors.send(format!("Failed to decode music: {}", err)).ok();
+                self.failed = true;
+                None
+            },
+        }
+    }
+
+    /// Continues decoding from sample `position`. Ogg can only seek to the start of a page, so the
+    /// rest of the way there is decoded and skipped.
+    fn seek(&mut self, position: usize) {
+        self.failed = false;
+        let channels = self.reader.ident_hdr.audio_channels as usize;
+        let frame = (position / channels) as u64;
+
+        // Seek further back each time the page found starts past the position or tells nothing
+        let mut margin = 1u64 << self.reader.ident_hdr.blocksize_1;
+        let mut skip = loop {
+            match frame.checked_sub(margin) {
+                Some(before) => match self.seek_page(before, channels) {
+                    Some(start) if start <= position => break position - start,
+                    _ => margin *= 2,
+                },
+                None => {
+                    // Close to the beginning, so decode from there
+                    self.reader = OggStreamReader::new(Cursor::new(self.data)).unwrap();
+                    break position;
+                },
+            }
+        };
+
+        self.packet.clear();
+        while skip >= self.packet.len() {
+            skip -= self.packet.len();
+            match self.next_packet() {
+                Some(packet) => self.packet = packet,
+                None => {
+                    self.packet.clear();
+                    break;
+                },
+            }
+        }
+        self.offset = skip.min(self.packet.len());
+    }
+
+    /// Seeks to the page before `frame` and decodes until a packet ending a page gives the granule
+    /// position of the samples after it, returning the sample they start at. Returns `None` if that
+    /// can not be told for certain.
+    fn seek_page(&mut self, frame: u64, channels: usize) -> Option<usize> {
+        self.reader.seek_absgp_pg(frame).ok()?;
+
+        // lewton hands out the samples of a long block up to where the next one starts overlapping it,
+        // which is past its granule position when the next block is short. Which blocks were decoded
+        // is told by how many frames they gave, the first after seeking giving none at all.
+        let short = 1usize << self.reader.ident_hdr.blocksize_0 >> 1;
+        let long = 1usize << self.reader.ident_hdr.blocksize_1 >> 1;
+        let overlap = (long - short) / 2;
+
+        let mut previous = None;
+        let mut primed = false;
+        loop {
+            let frames = self.next_packet()?.len() / channels;
+            let current = if primed { Some(frames) } else { None };
+            primed = true;
+
+            match self.reader.get_last_absgp() {
+                Some(absgp) => {
+                    let ahead = match (current, previous) {
+                        (Some(x), _) if x == long + overlap => overlap,
+                        (Some(x), Some(previous)) if x == long && previous == short => overlap,
+                        (Some(_), Some(_)) => 0,
+                        (Some(x), None) if x != long => 0,
+                        _ => return None,
+                    };
+                    return Some((absgp as usize + ahead) * channels);
+                },
+                None => previous = current,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MUSIC: &[u8] = include_bytes!("../../examples/01-raymarch/music.ogg");
+
+    /// Reads samples from `position` on until `len` of them have been decoded.
+    fn read(stream: &mut Stream, position: usize, len: usize) -> Vec<i16> {
+        for _ in 0..1000 {
+            let samples = stream.get(position, len);
+            if samples.len() == len {
+                return samples.to_vec();
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("The decoder did not catch up");
+    }
+
+    #[test]
+    fn streamed_samples_match_decoded_ones() {
+        let (errors, received) = mpsc::channel();
+        let mut stream = Stream::new(MUSIC, errors).unwrap();
+        let decoded = stream.decode_all();
+        assert_eq!(2, stream.channels());
+
+        // Playing through more than the ring holds
+        let mut position = 0;
+        while position + 4096 <= decoded.len() && position < 200_000 {
+            assert_eq!(decoded[position..position + 4096], read(&mut stream, position, 4096)[..]);
+            position += 4096;
+        }
+
+        // Seeking anywhere gives the same samples as decoding everything, down to the sample
+        for position in [600_000, 1_000, 123_456, 0, 400_002] {
+            stream.seek(position);
+            assert_eq!(decoded[position..position + 1024], read(&mut stream, position, 1024)[..]);
+        }
+        assert_eq!(None, received.try_recv().ok());
+    }
+
+    #[test]
+    fn decode_errors_are_reported() {
+        // The headers are intact, but the music is cut off in the middle of a packet
+        let cut = &MUSIC[..MUSIC.len() / 2];
+        let (errors, received) = mpsc::channel();
+        let stream = Stream::new(cut, errors).unwrap();
+        stream.decode_all();
+        assert!(received.try_recv().unwrap().starts_with("Failed to decode music"));
+    }
+}
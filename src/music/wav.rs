@@ -0,0 +1,177 @@
+//! Reader for WAV files as rendered by music software, converting their samples to 16-bit.
+
+use std::io;
+
+const PCM: u16 = 1;
+const FLOAT: u16 = 3;
+/// Format given by a GUID at the end of the format chunk instead, which starts with the actual one
+const EXTENSIBLE: u16 = 0xfffe;
+
+pub(super) struct Wav {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Interleaved samples
+    pub samples: Vec<i16>,
+}
+
+struct Format {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Reads 16 and 24-bit integer or 32-bit float samples, with any number of channels.
+pub(super) fn read(data: &[u8]) -> io::Result<Wav> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(invalid("Not a WAV file".to_string()));
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let start = offset + 8;
+        // Files written while rendering may claim more data than there is
+        let end = data.len().min(start + u32_at(data, offset + 4) as usize);
+        let chunk = &data[start..end];
+
+        match id {
+            b"fmt " if chunk.len() >= 16 => {
+                let mut tag = u16_at(chunk, 0);
+                if tag == EXTENSIBLE && chunk.len() >= 26 {
+                    tag = u16_at(chunk, 24);
+                }
+                format = Some(Format {
+                    tag,
+                    channels: u16_at(chunk, 2),
+                    sample_rate: u32_at(chunk, 4),
+                    bits: u16_at(chunk, 14),
+                });
+            },
+            b"data" => {
+                let format = format.ok_or_else(|| invalid("The WAV data comes before its format".to_string()))?;
+                return Ok(Wav {
+                    sample_rate: format.sample_rate,
+                    channels: format.channels,
+                    samples: convert(&format, chunk)?,
+                });
+            },
+            _ => (),
+        }
+
+        // Chunks are padded to an even length
+        offset = end + (end - start) % 2;
+    }
+
+    Err(invalid("The WAV file has no data".to_string()))
+}
+
+fn convert(format: &Format, data: &[u8]) -> io::Result<Vec<i16>> {
+    if format.channels == 0 {
+        return Err(invalid("The WAV file has no channels".to_string()));
+    }
+
+    let samples = match (format.tag, format.bits) {
+        (PCM, 16) => data
+            .chunks_exact(2)
+            .map(|x| i16::from_le_bytes([x[0], x[1]]))
+            .collect::<Vec<_>>(),
+        // Only the most significant bytes are kept
+        (PCM, 24) => data
+            .chunks_exact(3)
+            .map(|x| i16::from_le_bytes([x[1], x[2]]))
+            .collect(),
+        (FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|x| (f32::from_le_bytes([x[0], x[1], x[2], x[3]]).clamp(-1f32, 1f32) * i16::MAX as f32).round() as i16)
+            .collect(),
+        (tag, bits) => return Err(invalid(format!("Unsupported WAV format {} with {} bits per sample", tag, bits))),
+    };
+
+    // A frame cut short at the end of a truncated file is dropped
+    let frames = samples.len() / format.channels as usize;
+    Ok(samples[..frames * format.channels as usize].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A WAV file with a format chunk of `fmt` and an odd sized chunk before the data, to be skipped.
+    fn wav(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", fmt), (b"LIST", &[1, 2, 3][..]), (b"data", data)] {
+            wav.extend_from_slice(id);
+            wav.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            wav.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                wav.push(0);
+            }
+        }
+        wav
+    }
+
+    fn fmt(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt
+    }
+
+    #[test]
+    fn samples_are_converted_to_16_bit() {
+        let wav16 = read(&wav(&fmt(PCM, 2, 44100, 16), &[1, 0, 0xff, 0xff])).unwrap();
+        assert_eq!((44100, 2), (wav16.sample_rate, wav16.channels));
+        assert_eq!(vec![1, -1], wav16.samples);
+
+        let wav24 = read(&wav(&fmt(PCM, 1, 48000, 24), &[0xff, 0x34, 0x12, 0, 0, 0x80, 0])).unwrap();
+        assert_eq!(vec![0x1234, i16::MIN], wav24.samples);
+
+        let mut data = Vec::new();
+        for sample in [0.5f32, -2f32, 1f32] {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        let float = read(&wav(&fmt(FLOAT, 3, 48000, 32), &data)).unwrap();
+        assert_eq!(vec![16384, -i16::MAX, i16::MAX], float.samples);
+    }
+
+    #[test]
+    fn extensible_format_is_read() {
+        let mut extensible = fmt(EXTENSIBLE, 6, 48000, 24);
+        extensible.extend_from_slice(&22u16.to_le_bytes());
+        extensible.extend_from_slice(&24u16.to_le_bytes());
+        extensible.extend_from_slice(&0x3fu32.to_le_bytes());
+        extensible.extend_from_slice(&PCM.to_le_bytes());
+        extensible.extend_from_slice(&[0; 14]);
+
+        // Six channels of 24-bit samples, then half a frame that is dropped
+        let wav = read(&wav(&extensible, &[0x10; 18 + 9])).unwrap();
+        assert_eq!(6, wav.channels);
+        assert_eq!(vec![0x1010; 6], wav.samples);
+    }
+
+    #[test]
+    fn unsupported_formats_are_rejected() {
+        assert!(read(b"OggS").is_err());
+        assert!(read(&wav(&fmt(PCM, 2, 44100, 8), &[0, 0])).is_err());
+        assert!(read(&wav(&fmt(FLOAT, 2, 44100, 64), &[0; 16])).is_err());
+    }
+}
@@ -1,63 +1,484 @@
-use std::{path::{Path, PathBuf}, io::{Write, Read}, time::Duration};
+use std::{path::{Path, PathBuf}, io::{self, Write, Read, Cursor}, ops::Range, time::Duration, fmt};
 
 #[cfg(feature = "editor")]
 use serde::{Deserialize, Serialize};
 
-use crate::{binary, time::{SeekableTimeSource, TimeSource}};
+use crate::{binary, time::{SeekableTimeSource, TimeSource}, DemoBuilder};
+
+mod block;
+mod group;
+mod history;
+mod looping;
+mod marker;
+#[cfg(feature = "editor")]
+pub mod rocket;
+mod timing;
+
+pub use block::{Clip, Selection};
+pub use group::Group;
+pub use marker::Marker;
+use history::{Change, Edit, History};
+pub use timing::{TempoChange, Timing};
+
+impl DemoBuilder {
+    pub fn with_tracker(mut self, tracker: Tracker) -> DemoBuilder {
+        self.demo.tracker = Some(tracker);
+        self
+    }
+}
+
+/// Identifies the binary sync format, followed by [`BINARY_VERSION`].
+const BINARY_MAGIC: &[u8; 4] = b"USCH";
+const BINARY_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    #[cfg(feature = "editor")]
+    Json(PathBuf, serde_json::Error),
+    Binary(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, err) => write!(f, "Failed to read sync data from {}: {}", path.display(), err),
+            #[cfg(feature = "editor")]
+            Error::Json(path, err) => write!(f, "Malformed sync data in {}: {}", path.display(), err),
+            Error::Binary(err) => write!(f, "Malformed binary sync data: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Layout of the editable sync file.
+#[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
+struct SyncFile<T> {
+    #[cfg_attr(feature = "editor", serde(default))]
+    timing: Timing,
+    #[cfg_attr(feature = "editor", serde(default))]
+    groups: Vec<Group>,
+    #[cfg_attr(feature = "editor", serde(default))]
+    markers: Vec<Marker>,
+    tracks: Vec<T>,
+}
+
+impl<T> Default for SyncFile<T> {
+    fn default() -> Self {
+        SyncFile {
+            timing: Timing::default(),
+            groups: Vec::new(),
+            markers: Vec::new(),
+            tracks: Vec::new(),
+        }
+    }
+}
 
 pub struct Tracker {
-    bpm: u32,
+    timing: Timing,
+    groups: Vec<Group>,
+    markers: Vec<Marker>,
     tracks: Vec<Track>,
+    /// Tracks found in the sync file that the demo did not ask for, kept so that saving does not lose them
+    orphans: Vec<Track>,
     path: Option<PathBuf>,
+    history: History,
+    loop_region: Option<Range<u32>>,
     pub time: SeekableTimeSource,
 }
 
 impl Tracker {
-    pub fn new(bpm: u32, path: Option<&Path>, track_names: &[&'static str]) -> Tracker {
+    /// Creates a tracker with the given tracks. In editor builds any keys already saved at `path` are
+    /// loaded, a missing file is treated as empty. Tracks are ordered by group, in the order the groups
+    /// were saved in or else first appear in, and keep their given order within each group.
+    pub fn new(bpm: u32, path: Option<&Path>, track_names: &[&str]) -> Result<Tracker, Error> {
+        #[cfg(feature = "editor")]
+        let file = match path {
+            Some(path) => Tracker::load(path)?,
+            None => SyncFile::default(),
+        };
+        #[cfg(not(feature = "editor"))]
+        let file = SyncFile::<Track>::default();
+
+        let (mut timing, mut loaded) = (file.timing, file.tracks);
+        timing.bpm = std::cmp::max(1, bpm);
+
         let tracks = track_names
             .iter()
-            .map(|x| Track
-            {
-                name: x.to_string(),
-                values: Vec::new(),
+            .map(|name| match loaded.iter().position(|x| x.name == *name) {
+                Some(index) => loaded.remove(index),
+                None => Track::new(name.to_string()),
             })
             .collect::<Vec<Track>>();
 
-        Tracker { bpm, tracks, path: path.map(|x| x.to_path_buf()), time: SeekableTimeSource::now() }
+        for orphan in &loaded {
+            println!(
+                "Sync track {} has {} keys but is not used by the demo",
+                orphan.name,
+                orphan.values.len(),
+            );
+        }
+
+        let mut tracker = Tracker {
+            timing,
+            groups: file.groups,
+            markers: file.markers,
+            tracks,
+            orphans: loaded,
+            path: path.map(|x| x.to_path_buf()),
+            history: History::new(history::DEFAULT_DEPTH),
+            loop_region: None,
+            time: SeekableTimeSource::now(),
+        };
+        tracker.arrange_groups();
+        Ok(tracker)
+    }
+
+    /// Creates a tracker from data written by [`Tracker::export`], intended for release builds where
+    /// the data is embedded using `include_bytes!`.
+    pub fn from_bytes(data: &[u8]) -> Result<Tracker, Error> {
+        let mut reader = Cursor::new(data);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(Error::Binary)?;
+        if &magic != BINARY_MAGIC {
+            return Err(Error::Binary(io::Error::new(io::ErrorKind::InvalidData, "Not usch sync data")));
+        }
+
+        let version: u16 = binary::read(&mut reader).map_err(Error::Binary)?;
+        if version == 0 || version > BINARY_VERSION {
+            return Err(Error::Binary(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported version {}, expected {} or older", version, BINARY_VERSION),
+            )));
+        }
+
+        let bpm: u32 = binary::read(&mut reader).map_err(Error::Binary)?;
+        // Version 1 had no timing settings and always used the defaults
+        let timing = match version {
+            1 => Timing::new(bpm),
+            _ => Timing::read(&mut reader, bpm).map_err(Error::Binary)?,
+        };
+        let num_tracks: u32 = binary::read(&mut reader).map_err(Error::Binary)?;
+        let tracks = (0..num_tracks)
+            .map(|_| Track::read(&mut reader))
+            .collect::<io::Result<Vec<Track>>>()
+            .map_err(Error::Binary)?;
+
+        let mut tracker = Tracker {
+            timing,
+            groups: Vec::new(),
+            markers: Vec::new(),
+            tracks,
+            orphans: Vec::new(),
+            path: None,
+            history: History::new(history::DEFAULT_DEPTH),
+            loop_region: None,
+            time: SeekableTimeSource::now(),
+        };
+        tracker.arrange_groups();
+        Ok(tracker)
+    }
+
+    /// Serializes the tracks (but not orphaned ones) to the compact binary format read by
+    /// [`Tracker::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        binary::write_bytes(&mut data, BINARY_MAGIC);
+        binary::write(&mut data, &BINARY_VERSION);
+        binary::write(&mut data, &self.timing.bpm);
+        self.timing.write(&mut data);
+        binary::write(&mut data, &(self.tracks.len() as u32));
+        for track in &self.tracks {
+            track.write(&mut data);
+        }
+        data
+    }
+
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    /// Where the editable sync data is stored, if anywhere.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
     }
 
     pub fn current_row(&self) -> u32 {
-        (self.time.elapsed().as_secs_f32() * self.rows_per_second() + 0.5) as u32
+        (self.row_at(self.time.elapsed()) + 0.5) as u32
     }
 
     pub fn get_time_from_row(&self, row: u32) -> Duration {
-        Duration::from_secs_f32(row as f32 / self.rows_per_second())
+        Duration::from_secs_f32(self.timing.seconds_at(row as f32))
+    }
+
+    /// Converts a point in time to a fractional row, suitable for [`Track::value_at`].
+    pub fn row_at(&self, time: Duration) -> f32 {
+        self.timing.row_at(time.as_secs_f32())
+    }
+
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    pub fn set_rows_per_beat(&mut self, rows_per_beat: u32) {
+        self.timing.rows_per_beat = std::cmp::max(1, rows_per_beat);
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    pub fn set_beats_per_bar(&mut self, beats_per_bar: u32) {
+        self.timing.beats_per_bar = std::cmp::max(1, beats_per_bar);
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    /// Changes the tempo from `row` and onwards, replacing any earlier change at the same row.
+    pub fn set_tempo_change(&mut self, row: u32, bpm: u32) {
+        self.timing.set_tempo_change(row, std::cmp::max(1, bpm));
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    pub fn remove_tempo_change(&mut self, row: u32) {
+        self.timing.tempo_changes.retain(|x| x.row != row);
+
+        #[cfg(feature = "editor")]
+        self.save();
     }
 
     pub fn tracks(&self) -> &[Track] {
         &self.tracks
     }
 
-    pub fn set_value(&mut self, track_name: &'static str, row: u32, value: f32) {
-        self.tracks
-            .iter_mut()
-            .find(|x| x.name == track_name)
-            .expect(&format!("Failed to find a track named {}", track_name))
-            .set_value(row, value);
-            
+    /// Tracks loaded from disk that no longer match any of the declared track names.
+    pub fn orphaned_tracks(&self) -> &[Track] {
+        &self.orphans
+    }
+
+    pub fn track(&self, track_name: &str) -> Option<&Track> {
+        self.tracks.iter().find(|x| x.name == track_name)
+    }
+
+    /// Adds an empty track, or brings back an orphaned track with the same name along with its keys.
+    /// Returns false if the name is empty or already taken.
+    pub fn add_track(&mut self, track_name: impl Into<String>) -> bool {
+        let name = track_name.into();
+        if name.is_empty() || self.track(&name).is_some() {
+            return false;
+        }
+
+        let track = match self.orphans.iter().position(|x| x.name == name) {
+            Some(index) => self.orphans.remove(index),
+            None => Track::new(name),
+        };
+        self.tracks.push(track);
+        self.arrange_groups();
+
+        #[cfg(feature = "editor")]
+        self.save();
+        true
+    }
+
+    /// Renames a track, moving it to another group if the prefix changes. Returns false if there is
+    /// no such track, or if the new name is empty or already taken.
+    pub fn rename_track(&mut self, track_name: &str, new_name: impl Into<String>) -> bool {
+        let new_name = new_name.into();
+        if new_name.is_empty() || self.track(&new_name).is_some() {
+            return false;
+        }
+        match self.track_index(track_name) {
+            Some(track) => self.tracks[track].name = new_name,
+            None => return false,
+        }
+        self.arrange_groups();
+
         #[cfg(feature = "editor")]
         self.save();
+        true
+    }
+
+    /// Removes a track and its keys, returning false if there is no such track.
+    pub fn remove_track(&mut self, track_name: &str) -> bool {
+        match self.track_index(track_name) {
+            Some(track) => {
+                self.tracks.remove(track);
+                self.history.remap(|x| match x.cmp(&track) {
+                    std::cmp::Ordering::Less => Some(x),
+                    std::cmp::Ordering::Equal => None,
+                    std::cmp::Ordering::Greater => Some(x - 1),
+                });
+            },
+            None => return false,
+        };
+        self.arrange_groups();
+
+        #[cfg(feature = "editor")]
+        self.save();
+        true
+    }
+
+    /// Evaluates the named track at a point in time, tracks that do not exist are always 0.
+    pub fn value_at(&self, track_name: &str, time: Duration) -> f32 {
+        match self.track(track_name) {
+            Some(track) => track.value_at(self.row_at(time)),
+            None => 0f32,
+        }
+    }
+
+    /// Sets the value of the key at `row`, adding a key if there is none. Like the other edits this
+    /// does nothing if there is no track with the name.
+    pub fn set_value(&mut self, track_name: &str, row: u32, value: f32) {
+        let track = match self.track_index(track_name) {
+            Some(track) => track,
+            None => return,
+        };
+        let interpolation = self.tracks[track].get_interpolation(row).unwrap_or_default();
+        self.edit(vec![(track, row, Some(Key { row, value, interpolation }))]);
+    }
+
+    /// Removes the key at `row`, if there is one.
+    pub fn delete_value(&mut self, track_name: &str, row: u32) {
+        match self.track_index(track_name) {
+            Some(track) => self.edit(vec![(track, row, None)]),
+            None => (),
+        }
+    }
+
+    /// Changes how the key at `row` interpolates towards the next one, if there is a key.
+    pub fn set_interpolation(&mut self, track_name: &str, row: u32, interpolation: Interpolation) {
+        let track = match self.track_index(track_name) {
+            Some(track) => track,
+            None => return,
+        };
+        match self.tracks[track].get_key(row) {
+            Some(key) => self.edit(vec![(track, row, Some(Key { interpolation, ..key }))]),
+            None => (),
+        }
+    }
+
+    /// Moves the key at `from_row` to `to_row` and gives it a new value, keeping its interpolation.
+    pub fn move_key(&mut self, track_name: &str, from_row: u32, to_row: u32, value: f32) {
+        let track = match self.track_index(track_name) {
+            Some(track) => track,
+            None => return,
+        };
+        match self.tracks[track].get_key(from_row) {
+            Some(key) => self.edit(vec![
+                (track, from_row, None),
+                (track, to_row, Some(Key { row: to_row, value, ..key })),
+            ]),
+            None => (),
+        }
+    }
+
+    /// Reverts the latest edit, returning false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo() {
+            Some(edit) => {
+                for change in edit.iter().rev() {
+                    self.tracks[change.track].replace_key(change.row, change.before);
+                }
+            },
+            None => return false,
+        }
+
+        #[cfg(feature = "editor")]
+        self.save();
+        true
+    }
+
+    /// Reapplies the latest undone edit, returning false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo() {
+            Some(edit) => {
+                for change in edit {
+                    self.tracks[change.track].replace_key(change.row, change.after);
+                }
+            },
+            None => return false,
+        }
+
+        #[cfg(feature = "editor")]
+        self.save();
+        true
+    }
+
+    /// Limits how many edits can be undone, 256 by default.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth);
+    }
+
+    /// Applies a set of (track index, row, new key) changes as a single undoable edit.
+    fn edit(&mut self, changes: Vec<(usize, u32, Option<Key>)>) {
+        let edit = changes
+            .into_iter()
+            .filter_map(|(track, row, after)| {
+                let before = self.tracks[track].get_key(row);
+                self.tracks[track].replace_key(row, after);
+                if before == after {
+                    None
+                } else {
+                    Some(Change { track, row, before, after })
+                }
+            })
+            .collect::<Edit>();
+
+        if edit.is_empty() {
+            return;
+        }
+        self.history.record(edit);
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    fn track_index(&self, track_name: &str) -> Option<usize> {
+        self.tracks.iter().position(|x| x.name == track_name)
     }
 
-    fn rows_per_second(&self) -> f32 {
-        (self.bpm as f32 / 60f32) * 4f32
+    #[cfg(feature = "editor")]
+    fn load(path: &Path) -> Result<SyncFile<Track>, Error> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(SyncFile::default()),
+            Err(err) => return Err(Error::Io(path.to_path_buf(), err)),
+        };
+
+        // Sync files used to be nothing but the list of tracks
+        let file = if json.trim_start().starts_with('[') {
+            serde_json::from_str(&json).map(|tracks| SyncFile { tracks, ..SyncFile::default() })
+        } else {
+            serde_json::from_str::<SyncFile<Track>>(&json)
+        };
+        let mut file = file.map_err(|err| Error::Json(path.to_path_buf(), err))?;
+
+        // Only the first of any keys or markers on the same row is kept, as editing would have done
+        for track in &mut file.tracks {
+            track.values.sort_by_key(|x| x.row);
+            track.values.dedup_by_key(|x| x.row);
+        }
+        file.timing.sanitize();
+        file.markers.sort_by_key(|x| x.row);
+        file.markers.dedup_by_key(|x| x.row);
+        Ok(file)
     }
 
     #[cfg(feature = "editor")]
     fn save(&self) {
         match &self.path {
             Some(path) => {
-                let json = serde_json::to_string_pretty(&self.tracks).unwrap();
+                let file = SyncFile {
+                    timing: self.timing.clone(),
+                    groups: self.groups.clone(),
+                    markers: self.markers.clone(),
+                    tracks: self.tracks.iter().chain(&self.orphans).collect::<Vec<&Track>>(),
+                };
+                let json = serde_json::to_string_pretty(&file).unwrap();
                 std::fs::write(path, json).unwrap();
             },
             None => (),
@@ -72,24 +493,104 @@ pub struct Track {
     values: Vec<Key>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
-struct Key {
+pub struct Key {
     row: u32,
     value: f32,
+    #[cfg_attr(feature = "editor", serde(default))]
+    interpolation: Interpolation,
 }
 
-impl Key {
-    fn read(mut reader: impl Read) -> Key {
-        Key {
-            row: binary::read(&mut reader),
-            value: binary::read(&mut reader),
+/// How a track moves from a key towards the next one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "editor", serde(rename_all = "lowercase"))]
+pub enum Interpolation {
+    /// Hold the value until the next key.
+    #[default]
+    Step,
+    Linear,
+    /// Smoothstep, eases in and out of both keys.
+    Smooth,
+    /// Quadratic ease-in, same as the "ramp" type in GNU Rocket.
+    Ramp,
+}
+
+impl Interpolation {
+    /// Maps the linear progress `t` (0..1) between two keys to the interpolated progress.
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Interpolation::Step => 0f32,
+            Interpolation::Linear => t,
+            Interpolation::Smooth => t * t * (3f32 - 2f32 * t),
+            Interpolation::Ramp => t * t,
+        }
+    }
+
+    /// The interpolation that follows this one, for cycling through them in the editor.
+    pub fn next(self) -> Interpolation {
+        match self {
+            Interpolation::Step => Interpolation::Linear,
+            Interpolation::Linear => Interpolation::Smooth,
+            Interpolation::Smooth => Interpolation::Ramp,
+            Interpolation::Ramp => Interpolation::Step,
+        }
+    }
+
+    /// Numbering used by the binary format, matching the key types of GNU Rocket.
+    pub(crate) fn from_u8(value: u8) -> Option<Interpolation> {
+        match value {
+            0 => Some(Interpolation::Step),
+            1 => Some(Interpolation::Linear),
+            2 => Some(Interpolation::Smooth),
+            3 => Some(Interpolation::Ramp),
+            _ => None,
         }
     }
 
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Interpolation::Step => 0,
+            Interpolation::Linear => 1,
+            Interpolation::Smooth => 2,
+            Interpolation::Ramp => 3,
+        }
+    }
+}
+
+impl Key {
+    pub fn row(&self) -> u32 {
+        self.row
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    fn read(mut reader: impl Read) -> io::Result<Key> {
+        let row = binary::read(&mut reader)?;
+        let value = binary::read(&mut reader)?;
+        let interpolation: u8 = binary::read(&mut reader)?;
+
+        Ok(Key {
+            row,
+            value,
+            interpolation: Interpolation::from_u8(interpolation).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown interpolation type {}", interpolation),
+            ))?,
+        })
+    }
+
     fn write(&self, mut writer: impl Write) {
         binary::write(&mut writer, &self.row);
         binary::write(&mut writer, &self.value);
+        binary::write(&mut writer, &self.interpolation.to_u8());
     }
 }
 
@@ -102,6 +603,21 @@ impl Track {
         &self.name
     }
 
+    /// The part of the name before the first `:`, or an empty string for tracks without a group.
+    pub fn group(&self) -> &str {
+        self.name.split_once(':').map_or("", |x| x.0)
+    }
+
+    /// The name without the group prefix.
+    pub fn short_name(&self) -> &str {
+        self.name.split_once(':').map_or(&self.name, |x| x.1)
+    }
+
+    /// All keys of the track, ordered by row.
+    pub fn keys(&self) -> &[Key] {
+        &self.values
+    }
+
     pub fn get_value(&self, row: u32) -> Option<f32> {
         self.values
             .iter()
@@ -109,37 +625,77 @@ impl Track {
             .map(|x| x.value)
     }
 
-    fn set_value(&mut self, row: u32, value: f32) {
-        match self.values.iter_mut().find(|x| x.row == row) {
-            Some(key) => key.value = value,
-            None => {
-                // This could be improved, but not a priority since it should not run in releases
-                self.values.push(Key {
-                    row,
-                    value,
-                });
-                self.values.sort_by_key(|x| x.row);
-            }
+    /// The interpolation of the key at `row`, if there is one.
+    pub fn get_interpolation(&self, row: u32) -> Option<Interpolation> {
+        self.values
+            .iter()
+            .find(|x| x.row == row)
+            .map(|x| x.interpolation)
+    }
+
+    /// Evaluates the track at a fractional row, interpolating between the surrounding keys.
+    /// Rows before the first key take its value, as do rows after the last key.
+    pub fn value_at(&self, row: f32) -> f32 {
+        // Index of the first key strictly after the requested row
+        let next = self.values.partition_point(|x| x.row as f32 <= row);
+        if next == 0 {
+            return self.values.first().map_or(0f32, |x| x.value);
         }
+        if next == self.values.len() {
+            return self.values[next - 1].value;
+        }
+
+        let (a, b) = (&self.values[next - 1], &self.values[next]);
+        let t = (row - a.row as f32) / (b.row - a.row) as f32;
+        a.value + (b.value - a.value) * a.interpolation.apply(t)
     }
 
-    fn read(mut reader: impl Read) -> Track {
-        let name_len: u16 = binary::read(&mut reader);
-        let mut name_buf = Vec::with_capacity(name_len as usize);
-        reader.read_exact(&mut name_buf).unwrap();
+    fn get_key(&self, row: u32) -> Option<Key> {
+        self.values
+            .iter()
+            .find(|x| x.row == row)
+            .copied()
+    }
+
+    fn set_key(&mut self, row: u32, value: f32, interpolation: Interpolation) {
+        self.replace_key(row, Some(Key { row, value, interpolation }));
+    }
 
-        let num_keys = binary::read(&mut reader);
-        let mut values = Vec::with_capacity(num_keys as usize);
+    fn delete_key(&mut self, row: u32) {
+        self.replace_key(row, None);
+    }
+
+    /// Sets or removes the key at `row`.
+    fn replace_key(&mut self, row: u32, key: Option<Key>) {
+        match (self.values.binary_search_by_key(&row, |x| x.row), key) {
+            (Ok(index), Some(key)) => self.values[index] = key,
+            (Ok(index), None) => { self.values.remove(index); },
+            (Err(index), Some(key)) => self.values.insert(index, key),
+            (Err(_), None) => (),
+        }
+    }
+
+    fn read(mut reader: impl Read) -> io::Result<Track> {
+        let name_len: u16 = binary::read(&mut reader)?;
+        let mut name_buf = vec![0u8; name_len as usize];
+        reader.read_exact(&mut name_buf)?;
+        let name = String::from_utf8(name_buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        // The count is not trusted for preallocation, truncated data fails when reading the keys
+        let num_keys: u32 = binary::read(&mut reader)?;
+        let mut values = Vec::new();
         for _ in 0..num_keys {
-            values.push(Key::read(&mut reader));
+            values.push(Key::read(&mut reader)?);
         }
 
-        Track { name: String::from_utf8(name_buf).unwrap(), values }
+        Ok(Track { name, values })
     }
 
     fn write(&self, mut writer: impl Write) {
         binary::write(&mut writer, &(self.name.len() as u16));
         binary::write_bytes(&mut writer, self.name.as_bytes());
+        binary::write(&mut writer, &(self.values.len() as u32));
 
         for value in &self.values {
             value.write(&mut writer);
@@ -149,8 +705,6 @@ impl Track {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
-
     use super::*;
 
     #[test]
@@ -158,6 +712,7 @@ mod tests {
         let input = Key {
             row: 0xDEADBEEF,
             value: 1337.69f32,
+            interpolation: Interpolation::Step,
         };
 
         // Write to binary
@@ -167,9 +722,257 @@ mod tests {
 
         // Read back into struct
         cursor.set_position(0);
-        let result = Key::read(cursor);
+        let result = Key::read(cursor).unwrap();
 
         assert_eq!(input.row, result.row);
         assert_eq!(input.value, result.value);
     }
+
+    #[test]
+    fn tracker_can_save_and_load_binary() {
+        let mut input = Tracker::new(140, None, &["foo", "bar"]).unwrap();
+        input.set_rows_per_beat(3);
+        input.set_tempo_change(64, 70);
+        input.tracks[0] = track(&[(0, 1f32, Interpolation::Smooth), (16, -2.5f32, Interpolation::Ramp)]);
+        input.tracks[1].set_key(4, 1337f32, Interpolation::Step);
+
+        let result = Tracker::from_bytes(&input.to_bytes()).unwrap();
+
+        assert_eq!(input.timing, result.timing);
+        assert_eq!(2, result.tracks().len());
+        assert_eq!("test", result.tracks()[0].name());
+        assert_eq!(Interpolation::Ramp, result.tracks()[0].values[1].interpolation);
+        assert_eq!(input.tracks()[0].value_at(5f32), result.tracks()[0].value_at(5f32));
+        assert_eq!("bar", result.tracks()[1].name());
+        assert_eq!(Some(1337f32), result.tracks()[1].get_value(4));
+    }
+
+    #[test]
+    fn tracker_rejects_malformed_binary() {
+        let mut data = Tracker::new(140, None, &["foo"]).unwrap().to_bytes();
+        data.truncate(data.len() - 1);
+        assert!(matches!(Tracker::from_bytes(&data), Err(Error::Binary(_))));
+
+        assert!(matches!(Tracker::from_bytes(b"RIFF\x01\x00"), Err(Error::Binary(_))));
+    }
+
+    fn track(keys: &[(u32, f32, Interpolation)]) -> Track {
+        Track {
+            name: "test".to_string(),
+            values: keys
+                .iter()
+                .map(|&(row, value, interpolation)| Key { row, value, interpolation })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn track_value_is_clamped_outside_of_keys() {
+        let track = track(&[(4, 1f32, Interpolation::Linear), (8, 2f32, Interpolation::Linear)]);
+
+        assert_eq!(1f32, track.value_at(0f32));
+        assert_eq!(2f32, track.value_at(100f32));
+        assert_eq!(0f32, Track::new("empty".to_string()).value_at(10f32));
+    }
+
+    #[test]
+    fn track_value_is_interpolated_between_keys() {
+        let step = track(&[(0, 0f32, Interpolation::Step), (4, 1f32, Interpolation::Step)]);
+        assert_eq!(0f32, step.value_at(3.9f32));
+        assert_eq!(1f32, step.value_at(4f32));
+
+        let linear = track(&[(0, 0f32, Interpolation::Linear), (4, 1f32, Interpolation::Step)]);
+        assert_eq!(0.25f32, linear.value_at(1f32));
+        assert_eq!(0.5f32, linear.value_at(2f32));
+
+        let smooth = track(&[(0, 0f32, Interpolation::Smooth), (4, 1f32, Interpolation::Step)]);
+        assert_eq!(0.15625f32, smooth.value_at(1f32));
+        assert_eq!(0.5f32, smooth.value_at(2f32));
+
+        let ramp = track(&[(0, 0f32, Interpolation::Ramp), (4, 1f32, Interpolation::Step)]);
+        assert_eq!(0.0625f32, ramp.value_at(1f32));
+        assert_eq!(0.25f32, ramp.value_at(2f32));
+    }
+
+    #[cfg(feature = "editor")]
+    #[test]
+    fn tracker_loads_saved_keys_and_keeps_orphans() {
+        let path = std::env::temp_dir().join("usch_tracker_loads_saved_keys.json");
+        std::fs::write(&path, r#"[
+            { "name": "foo", "values": [ { "row": 8, "value": 2.0 }, { "row": 4, "value": 1.0, "interpolation": "linear" } ] },
+            { "name": "old", "values": [ { "row": 0, "value": 3.0 } ] }
+        ]"#).unwrap();
+
+        let mut tracker = Tracker::new(120, Some(&path), &["foo", "bar"]).unwrap();
+        assert_eq!(2, tracker.tracks().len());
+        assert_eq!(Some(1f32), tracker.tracks()[0].get_value(4));
+        assert_eq!(1.5f32, tracker.tracks()[0].value_at(6f32));
+        assert_eq!("old", tracker.orphaned_tracks()[0].name());
+
+        // Saving keeps the orphaned track around for a later run
+        tracker.set_value("bar", 0, 5f32);
+        let tracker = Tracker::new(120, Some(&path), &["old"]).unwrap();
+        assert_eq!(Some(3f32), tracker.tracks()[0].get_value(0));
+        assert_eq!(2, tracker.orphaned_tracks().len());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "editor")]
+    #[test]
+    fn tracker_keeps_one_key_and_marker_per_row() {
+        let path = std::env::temp_dir().join("usch_tracker_keeps_one_key_per_row.json");
+        std::fs::write(&path, r#"{
+            "markers": [ { "row": 8, "name": "drop" }, { "row": 0, "name": "intro" }, { "row": 8, "name": "again" } ],
+            "tracks": [ { "name": "foo", "values": [ { "row": 4, "value": 1.0 }, { "row": 4, "value": 2.0 } ] } ]
+        }"#).unwrap();
+
+        let tracker = Tracker::new(120, Some(&path), &["foo"]).unwrap();
+        assert_eq!(1, tracker.tracks()[0].values.len());
+        assert_eq!(Some(1f32), tracker.tracks()[0].get_value(4));
+        assert_eq!(vec!["intro", "drop"], tracker.markers().iter().map(|x| x.name.as_str()).collect::<Vec<&str>>());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "editor")]
+    #[test]
+    fn tracker_fails_on_malformed_sync_data() {
+        let path = std::env::temp_dir().join("usch_tracker_fails_on_malformed.json");
+        std::fs::write(&path, "[ { \"name\": \"foo\" ").unwrap();
+
+        let result = Tracker::new(120, Some(&path), &["foo"]);
+        assert!(matches!(result, Err(Error::Json(..))));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tracker_edits_can_be_undone_and_redone() {
+        let mut tracker = Tracker::new(120, None, &["foo"]).unwrap();
+        tracker.set_value("foo", 0, 1f32);
+        tracker.set_value("foo", 0, 2f32);
+        tracker.set_interpolation("foo", 0, Interpolation::Linear);
+        tracker.delete_value("foo", 0);
+        // Deleting a key that does not exist does not create an edit
+        tracker.delete_value("foo", 1);
+
+        assert!(tracker.undo());
+        assert_eq!(Some(Interpolation::Linear), tracker.tracks()[0].get_interpolation(0));
+        assert!(tracker.undo());
+        assert!(tracker.undo());
+        assert_eq!(Some(1f32), tracker.tracks()[0].get_value(0));
+        assert!(tracker.undo());
+        assert_eq!(None, tracker.tracks()[0].get_value(0));
+        assert!(!tracker.undo());
+
+        assert!(tracker.redo());
+        assert!(tracker.redo());
+        assert_eq!(Some(2f32), tracker.tracks()[0].get_value(0));
+
+        // A new edit discards what was left to redo
+        tracker.set_value("foo", 4, 3f32);
+        assert!(!tracker.redo());
+    }
+
+    #[test]
+    fn tracker_history_is_bounded() {
+        let mut tracker = Tracker::new(120, None, &["foo"]).unwrap();
+        tracker.set_history_depth(2);
+        for row in 0..4 {
+            tracker.set_value("foo", row, row as f32);
+        }
+
+        assert!(tracker.undo());
+        assert!(tracker.undo());
+        assert!(!tracker.undo());
+        assert_eq!(Some(1f32), tracker.tracks()[0].get_value(1));
+    }
+
+    #[test]
+    fn key_can_be_moved_in_one_edit() {
+        let mut tracker = Tracker::new(120, None, &["foo"]).unwrap();
+        tracker.set_value("foo", 4, 1f32);
+        tracker.set_interpolation("foo", 4, Interpolation::Smooth);
+
+        tracker.move_key("foo", 4, 6, 2f32);
+        assert_eq!(None, tracker.tracks()[0].get_value(4));
+        assert_eq!(Some(2f32), tracker.tracks()[0].get_value(6));
+        assert_eq!(Some(Interpolation::Smooth), tracker.tracks()[0].get_interpolation(6));
+
+        tracker.undo();
+        assert_eq!(Some(1f32), tracker.tracks()[0].get_value(4));
+        assert_eq!(None, tracker.tracks()[0].get_value(6));
+    }
+
+    #[test]
+    fn tracks_can_be_added_renamed_and_removed() {
+        let mut tracker = Tracker::new(120, None, &["foo"]).unwrap();
+        assert!(tracker.add_track(String::from("fx:glow")));
+        assert!(!tracker.add_track("foo"));
+
+        tracker.set_value("fx:glow", 0, 1f32);
+        assert!(tracker.rename_track("fx:glow", "light:power"));
+        assert!(!tracker.rename_track("light:power", "foo"));
+        assert_eq!(1f32, tracker.value_at("light:power", Duration::ZERO));
+        assert_eq!("light", tracker.groups()[1].name());
+
+        assert!(tracker.remove_track("light:power"));
+        assert!(!tracker.remove_track("light:power"));
+        assert_eq!(1, tracker.groups().len());
+
+        // Unknown tracks are ignored when edited and evaluate to 0
+        tracker.set_value("light:power", 0, 1f32);
+        assert_eq!(0f32, tracker.value_at("light:power", Duration::ZERO));
+        assert_eq!(1, tracker.tracks().len());
+    }
+
+    #[cfg(feature = "editor")]
+    #[test]
+    fn tracker_saves_and_loads_timing() {
+        let path = std::env::temp_dir().join("usch_tracker_saves_and_loads_timing.json");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tracker = Tracker::new(120, Some(&path), &["foo"]).unwrap();
+        tracker.set_beats_per_bar(3);
+        tracker.set_tempo_change(32, 60);
+        tracker.set_value("foo", 8, 1f32);
+
+        let tracker = Tracker::new(140, Some(&path), &["foo"]).unwrap();
+        assert_eq!(140, tracker.timing().bpm());
+        assert_eq!(3, tracker.timing().beats_per_bar());
+        assert_eq!(&[TempoChange { row: 32, bpm: 60 }], tracker.timing().tempo_changes());
+        assert_eq!(Some(1f32), tracker.tracks()[0].get_value(8));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tracker_tempo_is_at_least_one_bpm() {
+        let tracker = Tracker::new(0, None, &["foo"]).unwrap();
+        assert_eq!(1, tracker.timing().bpm());
+        assert!(tracker.row_at(Duration::from_secs(1)).is_finite());
+    }
+
+    #[test]
+    #[cfg(feature = "editor")]
+    fn tracker_saves_and_loads_groups() {
+        let path = std::env::temp_dir().join("usch_tracker_saves_and_loads_groups.json");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tracker = Tracker::new(120, Some(&path), &["camera:x", "fx:glow"]).unwrap();
+        tracker.move_group("fx", -1);
+        tracker.set_group_collapsed("camera", true);
+        tracker.set_group_hidden("fx", true);
+
+        // Groups of new tracks are added at the end
+        let tracker = Tracker::new(120, Some(&path), &["camera:x", "fx:glow", "light:power"]).unwrap();
+        let groups = tracker.groups().iter().map(|x| x.name()).collect::<Vec<&str>>();
+        assert_eq!(vec!["fx", "camera", "light"], groups);
+        assert!(tracker.groups()[0].is_hidden());
+        assert!(tracker.groups()[1].is_collapsed());
+        assert_eq!("fx:glow", tracker.tracks()[0].name());
+
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,243 @@
+//! Operations on rectangular blocks of keys, each applied to a [`Tracker`] as a single undoable edit.
+
+use std::{cmp::{max, min}, ops::Range};
+
+use super::{Key, Tracker};
+
+/// A block of rows across a set of tracks, given by their indices in the order they are shown. The
+/// tracks do not have to be next to each other, so that tracks hidden in the editor can be skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub tracks: Vec<usize>,
+    pub rows: Range<u32>,
+}
+
+impl Selection {
+    /// The selection spanning two (column, row) corners, both of them included, where `columns`
+    /// maps columns to track indices.
+    pub fn between(columns: &[usize], (column_a, row_a): (usize, u32), (column_b, row_b): (usize, u32)) -> Selection {
+        let end = min(max(column_a, column_b) + 1, columns.len());
+        Selection {
+            tracks: columns[min(min(column_a, column_b), end)..end].to_vec(),
+            rows: min(row_a, row_b)..max(row_a, row_b) + 1,
+        }
+    }
+
+    pub fn contains(&self, track: usize, row: u32) -> bool {
+        self.tracks.contains(&track) && self.rows.contains(&row)
+    }
+}
+
+/// Keys copied out of a tracker, with tracks and rows relative to the top left of the selection.
+#[derive(Debug, Clone, Default)]
+pub struct Clip {
+    tracks: usize,
+    rows: u32,
+    keys: Vec<(usize, Key)>,
+}
+
+impl Clip {
+    pub fn is_empty(&self) -> bool {
+        self.tracks == 0 || self.rows == 0
+    }
+}
+
+type Changes = Vec<(usize, u32, Option<Key>)>;
+
+impl Tracker {
+    pub fn copy(&self, selection: &Selection) -> Clip {
+        Clip {
+            tracks: selection.tracks.len(),
+            rows: selection.rows.len() as u32,
+            keys: self
+                .keys_in(selection)
+                .map(|(track, key)| {
+                    let column = selection.tracks.iter().position(|x| *x == track).unwrap();
+                    (column, Key { row: key.row - selection.rows.start, ..key })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn cut(&mut self, selection: &Selection) -> Clip {
+        let clip = self.copy(selection);
+        self.clear(selection);
+        clip
+    }
+
+    /// Removes all keys in the selection.
+    pub fn clear(&mut self, selection: &Selection) {
+        let changes = self.clear_changes(selection);
+        self.edit(changes);
+    }
+
+    /// Replaces the area covered by the clip, starting at `row` of the first of `tracks`. Columns of
+    /// the clip beyond the given tracks are ignored.
+    pub fn paste(&mut self, tracks: &[usize], row: u32, clip: &Clip) {
+        let changes = self.paste_changes(tracks, row, clip);
+        self.edit(changes);
+    }
+
+    /// Moves the selected keys by a number of rows, replacing whatever was in the destination.
+    pub fn move_block(&mut self, selection: &Selection, offset: i64) {
+        let row = max(0, selection.rows.start as i64 + offset) as u32;
+        let clip = self.copy(selection);
+
+        let mut changes = self.clear_changes(selection);
+        changes.extend(self.paste_changes(&selection.tracks, row, &clip));
+        self.edit(changes);
+    }
+
+    /// Shifts all keys from `row` and onwards down by `count` rows. Keys shifted past the last row
+    /// are dropped.
+    pub fn insert_rows(&mut self, tracks: &[usize], row: u32, count: u32) {
+        let selection = Selection { tracks: tracks.to_vec(), rows: row..u32::MAX };
+        let moved = self.keys_in(&selection).collect::<Vec<(usize, Key)>>();
+
+        let mut changes = self.clear_changes(&selection);
+        changes.extend(moved.into_iter().filter_map(|(track, key)| {
+            let row = key.row.checked_add(count)?;
+            Some((track, row, Some(Key { row, ..key })))
+        }));
+        self.edit(changes);
+    }
+
+    /// Removes the keys of `count` rows starting at `row`, shifting the keys below them up.
+    pub fn delete_rows(&mut self, tracks: &[usize], row: u32, count: u32) {
+        let selection = Selection { tracks: tracks.to_vec(), rows: row..u32::MAX };
+        let moved = self
+            .keys_in(&selection)
+            .filter(|(_, key)| key.row >= row.saturating_add(count))
+            .collect::<Vec<(usize, Key)>>();
+
+        let mut changes = self.clear_changes(&selection);
+        changes.extend(moved.into_iter().map(|(track, key)| {
+            (track, key.row - count, Some(Key { row: key.row - count, ..key }))
+        }));
+        self.edit(changes);
+    }
+
+    /// Sets the value of every selected key to `value * scale + offset`.
+    pub fn scale_values(&mut self, selection: &Selection, scale: f32, offset: f32) {
+        let changes = self
+            .keys_in(selection)
+            .map(|(track, key)| (track, key.row, Some(Key { value: key.value * scale + offset, ..key })))
+            .collect();
+        self.edit(changes);
+    }
+
+    fn keys_in<'a>(&'a self, selection: &'a Selection) -> impl Iterator<Item = (usize, Key)> + 'a {
+        self.tracks
+            .iter()
+            .enumerate()
+            .filter(|(track, _)| selection.tracks.contains(track))
+            .flat_map(|(track, x)| x.values.iter().map(move |key| (track, *key)))
+            .filter(|(_, key)| selection.rows.contains(&key.row))
+    }
+
+    fn clear_changes(&self, selection: &Selection) -> Changes {
+        self.keys_in(selection)
+            .map(|(track, key)| (track, key.row, None))
+            .collect()
+    }
+
+    fn paste_changes(&self, tracks: &[usize], row: u32, clip: &Clip) -> Changes {
+        let tracks = &tracks[..min(clip.tracks, tracks.len())];
+        let mut changes = self.clear_changes(&Selection { tracks: tracks.to_vec(), rows: row..row.saturating_add(clip.rows) });
+        changes.extend(clip.keys
+            .iter()
+            .filter(|(column, _)| *column < tracks.len())
+            .filter_map(|(column, key)| {
+                let row = row.checked_add(key.row)?;
+                Some((tracks[*column], row, Some(Key { row, ..*key })))
+            }));
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(keys: &[(&'static str, u32, f32)]) -> Tracker {
+        let mut tracker = Tracker::new(120, None, &["a", "b", "c"]).unwrap();
+        for (track, row, value) in keys {
+            tracker.set_value(track, *row, *value);
+        }
+        tracker
+    }
+
+    fn values(tracker: &Tracker, track: usize) -> Vec<(u32, f32)> {
+        tracker.tracks()[track].values.iter().map(|x| (x.row, x.value)).collect()
+    }
+
+    #[test]
+    fn block_can_be_copied_and_pasted() {
+        let mut tracker = tracker(&[("a", 0, 1f32), ("a", 2, 2f32), ("b", 1, 3f32), ("b", 4, 4f32)]);
+
+        let clip = tracker.copy(&Selection::between(&[0, 1, 2], (0, 0), (1, 2)));
+        tracker.set_value("c", 9, 5f32);
+        tracker.paste(&[1, 2], 8, &clip);
+
+        // The pasted area is replaced, and the part of the clip beyond the last track is dropped
+        assert_eq!(vec![(1, 3f32), (4, 4f32), (8, 1f32), (10, 2f32)], values(&tracker, 1));
+        assert_eq!(vec![(9, 3f32)], values(&tracker, 2));
+
+        // Pasting is a single edit
+        tracker.undo();
+        assert_eq!(vec![(9, 5f32)], values(&tracker, 2));
+    }
+
+    #[test]
+    fn rows_can_be_inserted_and_deleted() {
+        let mut tracker = tracker(&[("a", 0, 1f32), ("a", 4, 2f32), ("a", 8, 3f32), ("b", 4, 4f32)]);
+
+        tracker.insert_rows(&[0], 4, 2);
+        assert_eq!(vec![(0, 1f32), (6, 2f32), (10, 3f32)], values(&tracker, 0));
+        assert_eq!(vec![(4, 4f32)], values(&tracker, 1));
+
+        tracker.delete_rows(&[0], 5, 2);
+        assert_eq!(vec![(0, 1f32), (8, 3f32)], values(&tracker, 0));
+
+        tracker.undo();
+        tracker.undo();
+        assert_eq!(vec![(0, 1f32), (4, 2f32), (8, 3f32)], values(&tracker, 0));
+
+        // Keys shifted past the last row are dropped rather than wrapping around
+        tracker.set_value("b", u32::MAX - 1, 5f32);
+        tracker.insert_rows(&[1], 4, 2);
+        assert_eq!(vec![(6, 4f32)], values(&tracker, 1));
+        tracker.delete_rows(&[1], u32::MAX - 1, 2);
+        assert_eq!(vec![(6, 4f32)], values(&tracker, 1));
+    }
+
+    #[test]
+    fn block_can_be_moved_and_scaled() {
+        let mut tracker = tracker(&[("a", 2, 1f32), ("a", 3, 2f32), ("a", 5, 3f32)]);
+
+        tracker.move_block(&Selection::between(&[0], (0, 2), (0, 3)), 2);
+        assert_eq!(vec![(4, 1f32), (5, 2f32)], values(&tracker, 0));
+
+        tracker.scale_values(&Selection::between(&[0], (0, 0), (0, 4)), 2f32, 1f32);
+        assert_eq!(vec![(4, 3f32), (5, 2f32)], values(&tracker, 0));
+
+        tracker.undo();
+        tracker.undo();
+        assert_eq!(vec![(2, 1f32), (3, 2f32), (5, 3f32)], values(&tracker, 0));
+    }
+
+    #[test]
+    fn selection_skips_tracks_that_are_not_shown() {
+        let mut tracker = tracker(&[("a", 0, 1f32), ("b", 0, 2f32), ("c", 0, 3f32)]);
+
+        // Track "b" is hidden, so the columns map to tracks "a" and "c"
+        let selection = Selection::between(&[0, 2], (0, 0), (1, 0));
+        let clip = tracker.copy(&selection);
+        tracker.clear(&selection);
+        assert_eq!(vec![(0, 2f32)], values(&tracker, 1));
+
+        tracker.paste(&[0, 2], 4, &clip);
+        assert_eq!(vec![(4, 1f32)], values(&tracker, 0));
+        assert_eq!(vec![(4, 3f32)], values(&tracker, 2));
+    }
+}
@@ -0,0 +1,172 @@
+//! Grouping of tracks by the prefix of their names, such as `camera` for `camera:pos_x`, so that the
+//! editor can show, hide, collapse and order them together.
+
+#[cfg(feature = "editor")]
+use serde::{Deserialize, Serialize};
+
+use super::{Track, Tracker};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
+pub struct Group {
+    name: String,
+    #[cfg_attr(feature = "editor", serde(default))]
+    hidden: bool,
+    #[cfg_attr(feature = "editor", serde(default))]
+    collapsed: bool,
+}
+
+impl Group {
+    /// The shared prefix of the tracks in the group, empty for tracks without one.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_collapsed(&self) -> bool {
+        self.collapsed
+    }
+}
+
+impl Tracker {
+    /// Groups in the order they are shown, tracks are kept in the same order.
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    pub fn set_group_hidden(&mut self, name: &str, hidden: bool) {
+        match self.groups.iter_mut().find(|x| x.name == name) {
+            Some(group) => group.hidden = hidden,
+            None => return,
+        }
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    pub fn set_group_collapsed(&mut self, name: &str, collapsed: bool) {
+        match self.groups.iter_mut().find(|x| x.name == name) {
+            Some(group) => group.collapsed = collapsed,
+            None => return,
+        }
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    /// Moves a group towards the front (negative offset) or the back of the order.
+    pub fn move_group(&mut self, name: &str, offset: isize) {
+        let from = match self.groups.iter().position(|x| x.name == name) {
+            Some(index) => index,
+            None => return,
+        };
+        let to = (from as isize + offset).clamp(0, self.groups.len() as isize - 1) as usize;
+        let group = self.groups.remove(from);
+        self.groups.insert(to, group);
+        self.arrange_groups();
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    /// Adds groups for tracks that are not part of one yet, drops groups without tracks, and orders
+    /// the tracks by group.
+    pub(super) fn arrange_groups(&mut self) {
+        let tracks = &self.tracks;
+        self.groups.retain(|group| tracks.iter().any(|x| x.group() == group.name));
+        for track in &self.tracks {
+            if !self.groups.iter().any(|x| x.name == track.group()) {
+                self.groups.push(Group {
+                    name: track.group().to_string(),
+                    hidden: false,
+                    collapsed: false,
+                });
+            }
+        }
+
+        let (groups, tracks) = (&self.groups, &self.tracks);
+        let mut order = (0..tracks.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|x| groups.iter().position(|group| group.name == tracks[*x].group()));
+
+        let mut tracks = std::mem::take(&mut self.tracks).into_iter().map(Some).collect::<Vec<Option<Track>>>();
+        self.tracks = order.iter().map(|x| tracks[*x].take().unwrap()).collect();
+
+        // The history refers to tracks by index
+        let mut moved = vec![0; order.len()];
+        for (to, from) in order.into_iter().enumerate() {
+            moved[from] = to;
+        }
+        self.history.remap(|x| Some(moved[x]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_are_ordered_by_group() {
+        let mut tracker = Tracker::new(120, None, &["camera:x", "fx:glow", "camera:y", "time"]).unwrap();
+        let names = |tracker: &Tracker| tracker.tracks().iter().map(|x| x.name().to_string()).collect::<Vec<String>>();
+
+        assert_eq!(vec!["camera:x", "camera:y", "fx:glow", "time"], names(&tracker));
+        assert_eq!("camera", tracker.tracks()[1].group());
+        assert_eq!("y", tracker.tracks()[1].short_name());
+        assert_eq!("", tracker.tracks()[3].group());
+
+        tracker.move_group("fx", -1);
+        assert_eq!(vec!["fx:glow", "camera:x", "camera:y", "time"], names(&tracker));
+
+        // Tracks are still found by their full name
+        tracker.set_value("camera:y", 0, 1f32);
+        assert_eq!(Some(1f32), tracker.tracks()[2].get_value(0));
+    }
+
+    #[test]
+    fn history_follows_groups_as_they_are_moved() {
+        let mut tracker = Tracker::new(120, None, &["camera:x", "fx:glow"]).unwrap();
+        tracker.set_value("camera:x", 0, 1f32);
+        tracker.set_value("fx:glow", 0, 2f32);
+
+        // Both tracks swap places, their edits are still undone on the right track
+        tracker.move_group("fx", -1);
+        tracker.undo();
+        assert_eq!(None, tracker.tracks()[0].get_value(0));
+        assert_eq!(Some(1f32), tracker.tracks()[1].get_value(0));
+        tracker.undo();
+        assert_eq!(None, tracker.tracks()[1].get_value(0));
+
+        tracker.redo();
+        tracker.set_group_hidden("camera", true);
+        tracker.redo();
+        assert_eq!(Some(2f32), tracker.tracks()[0].get_value(0));
+    }
+
+    #[test]
+    fn history_follows_tracks_as_they_are_added_and_removed() {
+        let mut tracker = Tracker::new(120, None, &["camera:x", "time"]).unwrap();
+        tracker.set_value("time", 0, 1f32);
+        tracker.set_value("camera:x", 0, 2f32);
+
+        let value = |tracker: &Tracker, name: &str| tracker.track(name).unwrap().get_value(0);
+
+        // The new track goes in between, moving the first edited one
+        tracker.add_track("camera:y");
+        tracker.undo();
+        assert_eq!(None, value(&tracker, "camera:x"));
+        tracker.undo();
+        assert_eq!(None, value(&tracker, "time"));
+
+        // Edits of a removed track are dropped, the others can still be undone and redone
+        tracker.redo();
+        tracker.redo();
+        tracker.remove_track("time");
+        tracker.undo();
+        assert_eq!(None, value(&tracker, "camera:x"));
+        tracker.redo();
+        assert_eq!(Some(2f32), value(&tracker, "camera:x"));
+    }
+}
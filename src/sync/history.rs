@@ -0,0 +1,81 @@
+//! Undo and redo of the edits made to the keys of a [`Tracker`](super::Tracker).
+
+use std::collections::VecDeque;
+
+use super::Key;
+
+pub const DEFAULT_DEPTH: usize = 256;
+
+/// The state of a single key slot before and after an edit, `None` meaning that there is no key.
+#[derive(Debug, Clone)]
+pub(super) struct Change {
+    pub track: usize,
+    pub row: u32,
+    pub before: Option<Key>,
+    pub after: Option<Key>,
+}
+
+/// Every user action, such as setting a key or pasting a block, becomes a single edit so that it is
+/// undone in one step.
+pub(super) type Edit = Vec<Change>;
+
+pub(super) struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    depth: usize,
+}
+
+impl History {
+    pub fn new(depth: usize) -> History {
+        History { undo: VecDeque::new(), redo: Vec::new(), depth }
+    }
+
+    /// Records a new edit, after which the undone edits can no longer be redone.
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        self.undo.push_back(edit);
+        self.trim();
+    }
+
+    /// Moves the latest edit to the redo stack and returns it, for its changes to be reverted.
+    pub fn undo(&mut self) -> Option<&Edit> {
+        let edit = self.undo.pop_back()?;
+        self.redo.push(edit);
+        self.redo.last()
+    }
+
+    /// Moves the latest undone edit back to the undo stack and returns it, for it to be reapplied.
+    pub fn redo(&mut self) -> Option<&Edit> {
+        let edit = self.redo.pop()?;
+        self.undo.push_back(edit);
+        self.undo.back()
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+
+    /// Points the changes at the new indices of their tracks after tracks have been reordered or
+    /// removed. Changes to removed tracks, for which `track` gives `None`, are dropped along with
+    /// edits that are left without changes.
+    pub fn remap(&mut self, track: impl Fn(usize) -> Option<usize>) {
+        for edit in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            *edit = edit
+                .drain(..)
+                .filter_map(|mut change| {
+                    change.track = track(change.track)?;
+                    Some(change)
+                })
+                .collect();
+        }
+        self.undo.retain(|x| !x.is_empty());
+        self.redo.retain(|x| !x.is_empty());
+    }
+
+    fn trim(&mut self) {
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Repeated playback of a range of rows while editing.
+
+use std::{ops::Range, time::Duration};
+
+use crate::time::TimeSource;
+
+use super::Tracker;
+
+impl Tracker {
+    /// The rows played repeatedly, the end not included.
+    pub fn loop_region(&self) -> Option<Range<u32>> {
+        self.loop_region.clone()
+    }
+
+    /// Loops the given rows, or stops looping if the range is empty.
+    pub fn set_loop(&mut self, rows: Range<u32>) {
+        self.loop_region = if rows.is_empty() { None } else { Some(rows) };
+    }
+
+    /// Starts the loop at `row`, keeping the current end if it comes after it and otherwise looping
+    /// a single bar.
+    pub fn set_loop_in(&mut self, row: u32) {
+        let end = match &self.loop_region {
+            Some(region) if region.end > row => region.end,
+            _ => row + self.timing.rows_per_bar(),
+        };
+        self.set_loop(row..end);
+    }
+
+    /// Ends the loop just before `row`, keeping the current start if it comes before it and
+    /// otherwise looping a single bar.
+    pub fn set_loop_out(&mut self, row: u32) {
+        let start = match &self.loop_region {
+            Some(region) if region.start < row => region.start,
+            _ => row.saturating_sub(self.timing.rows_per_bar()),
+        };
+        self.set_loop(start..row);
+    }
+
+    /// Seeks back into the loop once playback has passed its end, returning true if it did so that
+    /// music can follow.
+    pub fn wrap_loop(&mut self) -> bool {
+        let (start, end) = match &self.loop_region {
+            Some(region) => (self.timing.seconds_at(region.start as f32), self.timing.seconds_at(region.end as f32)),
+            None => return false,
+        };
+
+        let elapsed = self.time.elapsed().as_secs_f32();
+        if self.time.is_paused() || elapsed < end {
+            return false;
+        }
+
+        // Keep the overshoot so that the loop does not drift with the frame rate
+        self.time.seek(Duration::from_secs_f32(start + (elapsed - end) % (end - start)));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_region_follows_in_and_out_points() {
+        let mut tracker = Tracker::new(120, None, &[]).unwrap();
+        tracker.set_loop_in(16);
+        assert_eq!(Some(16..32), tracker.loop_region());
+
+        tracker.set_loop_out(24);
+        assert_eq!(Some(16..24), tracker.loop_region());
+
+        tracker.set_loop_out(8);
+        assert_eq!(Some(0..8), tracker.loop_region());
+
+        tracker.set_loop(4..4);
+        assert_eq!(None, tracker.loop_region());
+    }
+
+    #[test]
+    fn playback_wraps_to_loop_start() {
+        let mut tracker = Tracker::new(120, None, &[]).unwrap();
+        tracker.set_loop(16..32);
+
+        // 8 rows per second, so the loop runs from 2 to 4 seconds
+        tracker.time.seek(Duration::from_secs_f32(3f32));
+        assert!(!tracker.wrap_loop());
+
+        tracker.time.seek(Duration::from_secs_f32(4.5f32));
+        assert!(tracker.wrap_loop());
+        let row = tracker.row_at(tracker.time.elapsed());
+        assert!((20f32..21f32).contains(&row));
+
+        // Nothing moves while paused
+        tracker.time.seek(Duration::from_secs_f32(5f32));
+        tracker.time.set_paused(true);
+        assert!(!tracker.wrap_loop());
+    }
+}
@@ -0,0 +1,77 @@
+//! Named rows such as "intro" or "drop", used to find the way around the timeline in the editor.
+
+#[cfg(feature = "editor")]
+use serde::{Deserialize, Serialize};
+
+use super::Tracker;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
+pub struct Marker {
+    pub row: u32,
+    pub name: String,
+}
+
+impl Tracker {
+    /// Markers ordered by row.
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    /// Names `row`, replacing the name of any marker already there.
+    pub fn set_marker(&mut self, row: u32, name: impl Into<String>) {
+        let marker = Marker { row, name: name.into() };
+        match self.markers.binary_search_by_key(&row, |x| x.row) {
+            Ok(index) => self.markers[index] = marker,
+            Err(index) => self.markers.insert(index, marker),
+        }
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    pub fn remove_marker(&mut self, row: u32) {
+        self.markers.retain(|x| x.row != row);
+
+        #[cfg(feature = "editor")]
+        self.save();
+    }
+
+    pub fn marker_at(&self, row: u32) -> Option<&Marker> {
+        self.markers.iter().find(|x| x.row == row)
+    }
+
+    /// The first marker after `row`.
+    pub fn next_marker(&self, row: u32) -> Option<&Marker> {
+        self.markers.iter().find(|x| x.row > row)
+    }
+
+    /// The last marker before `row`.
+    pub fn previous_marker(&self, row: u32) -> Option<&Marker> {
+        self.markers.iter().rev().find(|x| x.row < row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_are_found_around_a_row() {
+        let mut tracker = Tracker::new(120, None, &[]).unwrap();
+        tracker.set_marker(64, "drop");
+        tracker.set_marker(0, "intro");
+        tracker.set_marker(128, "outro");
+        tracker.set_marker(64, "chorus");
+
+        let names = tracker.markers().iter().map(|x| x.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(vec!["intro", "chorus", "outro"], names);
+
+        assert_eq!(Some(128), tracker.next_marker(64).map(|x| x.row));
+        assert_eq!(Some(0), tracker.previous_marker(64).map(|x| x.row));
+        assert_eq!(None, tracker.previous_marker(0));
+
+        tracker.remove_marker(128);
+        assert_eq!(None, tracker.next_marker(64));
+    }
+}
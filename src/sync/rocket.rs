@@ -0,0 +1,302 @@
+//! Client for the GNU Rocket sync-tracker protocol, letting an external editor drive a [`Tracker`].
+//!
+//! The editor owns the keys: when connecting all local keys of the requested tracks are replaced by
+//! the ones sent by the editor, and they are only written to disk when the editor asks for it.
+
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}};
+
+use crate::DemoBuilder;
+
+use super::{Interpolation, Track, Tracker};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:1338";
+
+const CLIENT_GREET: &[u8] = b"hello, synctracker!";
+const SERVER_GREET: &[u8] = b"hello, demo!";
+
+const SET_KEY: u8 = 0;
+const DELETE_KEY: u8 = 1;
+const GET_TRACK: u8 = 2;
+const SET_ROW: u8 = 3;
+const PAUSE: u8 = 4;
+const SAVE_TRACKS: u8 = 5;
+
+impl DemoBuilder {
+    /// Connects the tracker to a running Rocket editor, must be called after `with_tracker`.
+    /// The demo runs without one if the editor can not be reached.
+    pub fn with_rocket(mut self, address: impl ToSocketAddrs) -> DemoBuilder {
+        let tracker = self.demo.tracker
+            .as_mut()
+            .expect("A tracker is required to connect to a Rocket editor");
+
+        match Client::connect(address, tracker) {
+            Ok(client) => self.demo.rocket = Some(client),
+            Err(err) => println!("Failed to connect to the Rocket editor: {}", err),
+        }
+        self
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    SetKey { track: u32, row: u32, value: f32, interpolation: Interpolation },
+    DeleteKey { track: u32, row: u32 },
+    SetRow(u32),
+    Pause(bool),
+    SaveTracks,
+}
+
+impl Command {
+    /// Parses the first command in `data`, returning it along with its length in bytes, or `None`
+    /// if more data is needed.
+    fn parse(data: &[u8]) -> io::Result<Option<(Command, usize)>> {
+        let u32_at = |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+
+        let (len, command) = match data.first() {
+            None => return Ok(None),
+            Some(&SET_KEY) => (14, SET_KEY),
+            Some(&DELETE_KEY) => (9, DELETE_KEY),
+            Some(&SET_ROW) => (5, SET_ROW),
+            Some(&PAUSE) => (2, PAUSE),
+            Some(&SAVE_TRACKS) => (1, SAVE_TRACKS),
+            Some(other) => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown Rocket command {}", other),
+            )),
+        };
+        if data.len() < len {
+            return Ok(None);
+        }
+
+        let command = match command {
+            SET_KEY => Command::SetKey {
+                track: u32_at(1),
+                row: u32_at(5),
+                value: f32::from_bits(u32_at(9)),
+                interpolation: Interpolation::from_u8(data[13]).ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown interpolation type {}", data[13]),
+                ))?,
+            },
+            DELETE_KEY => Command::DeleteKey { track: u32_at(1), row: u32_at(5) },
+            SET_ROW => Command::SetRow(u32_at(1)),
+            PAUSE => Command::Pause(data[1] != 0),
+            _ => Command::SaveTracks,
+        };
+        Ok(Some((command, len)))
+    }
+}
+
+pub struct Client {
+    stream: TcpStream,
+    /// Names of the tracks, indexed the way the editor refers to them
+    tracks: Vec<String>,
+    received: Vec<u8>,
+    /// Messages waiting for room in the socket's send buffer
+    sending: Vec<u8>,
+    /// The last row sent to or received from the editor
+    row: Option<u32>,
+}
+
+impl Client {
+    /// Performs the handshake and requests every track of the tracker from the editor.
+    pub fn connect(address: impl ToSocketAddrs, tracker: &mut Tracker) -> io::Result<Client> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+
+        stream.write_all(CLIENT_GREET)?;
+        let mut greet = [0u8; SERVER_GREET.len()];
+        stream.read_exact(&mut greet)?;
+        if greet != SERVER_GREET {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected greeting from the Rocket editor"));
+        }
+
+        // Track indices used by the editor are given by the order they are requested in, which is
+        // kept by name since the tracker may reorder its tracks
+        for track in &mut tracker.tracks {
+            track.values.clear();
+
+            let mut message = vec![GET_TRACK];
+            message.extend_from_slice(&(track.name.len() as u32).to_be_bytes());
+            message.extend_from_slice(track.name.as_bytes());
+            stream.write_all(&message)?;
+        }
+
+        stream.set_nonblocking(true)?;
+        let tracks = tracker.tracks.iter().map(|x| x.name.clone()).collect();
+        Ok(Client { stream, tracks, received: Vec::new(), sending: Vec::new(), row: None })
+    }
+
+    /// Applies everything received from the editor and reports row changes back to it. Returns true
+    /// if the editor paused, resumed or moved the playback position, so that music can follow.
+    pub fn update(&mut self, tracker: &mut Tracker) -> io::Result<bool> {
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "The Rocket editor disconnected")),
+                Ok(n) => self.received.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut time_changed = false;
+        let mut consumed = 0;
+        while let Some((command, len)) = Command::parse(&self.received[consumed..])? {
+            consumed += len;
+            time_changed |= self.apply(command, tracker)?;
+        }
+        self.received.drain(..consumed);
+
+        let row = tracker.current_row();
+        if self.row != Some(row) {
+            let mut message = vec![SET_ROW];
+            message.extend_from_slice(&row.to_be_bytes());
+            self.sending.extend_from_slice(&message);
+            self.row = Some(row);
+        }
+        self.flush()?;
+
+        Ok(time_changed)
+    }
+
+    /// Sends as much of what is waiting as the socket takes without blocking, the rest is sent by
+    /// later updates.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.sending.is_empty() {
+            match self.stream.write(&self.sending) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "The Rocket editor stopped taking messages")),
+                Ok(n) => {
+                    self.sending.drain(..n);
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, command: Command, tracker: &mut Tracker) -> io::Result<bool> {
+        match command {
+            Command::SetKey { track, row, value, interpolation } => {
+                match self.track(tracker, track)? {
+                    Some(track) => track.set_key(row, value, interpolation),
+                    None => (),
+                }
+            },
+            Command::DeleteKey { track, row } => {
+                match self.track(tracker, track)? {
+                    Some(track) => track.delete_key(row),
+                    None => (),
+                }
+            },
+            Command::SetRow(row) => {
+                tracker.time.seek(tracker.get_time_from_row(row));
+                self.row = Some(row);
+                return Ok(true);
+            },
+            Command::Pause(paused) => {
+                tracker.time.set_paused(paused);
+                return Ok(true);
+            },
+            Command::SaveTracks => tracker.save(),
+        }
+        Ok(false)
+    }
+
+    /// The track the editor refers to, or `None` if it was renamed or removed since connecting.
+    fn track<'a>(&self, tracker: &'a mut Tracker, track: u32) -> io::Result<Option<&'a mut Track>> {
+        let name = self.tracks.get(track as usize).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The Rocket editor referenced unknown track {}", track),
+        ))?;
+        Ok(tracker.tracks.iter_mut().find(|x| &x.name == name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed_once_complete() {
+        let mut data = vec![SET_KEY, 0, 0, 0, 1, 0, 0, 0, 8];
+        data.extend_from_slice(&1.5f32.to_bits().to_be_bytes());
+        data.push(2);
+
+        assert_eq!(None, Command::parse(&data[..13]).unwrap());
+        assert_eq!(
+            Some((Command::SetKey { track: 1, row: 8, value: 1.5f32, interpolation: Interpolation::Smooth }, 14)),
+            Command::parse(&data).unwrap(),
+        );
+        assert_eq!(Some((Command::Pause(true), 2)), Command::parse(&[PAUSE, 1, SET_ROW]).unwrap());
+        assert!(Command::parse(&[42]).is_err());
+    }
+
+    #[test]
+    fn client_follows_mock_editor() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let editor = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut greet = [0u8; CLIENT_GREET.len()];
+            stream.read_exact(&mut greet).unwrap();
+            assert_eq!(CLIENT_GREET, greet);
+            stream.write_all(SERVER_GREET).unwrap();
+
+            let mut get_track = [0u8; 8];
+            stream.read_exact(&mut get_track).unwrap();
+            assert_eq!([GET_TRACK, 0, 0, 0, 3, b'f', b'o', b'o'], get_track);
+
+            let mut message = vec![SET_KEY, 0, 0, 0, 0, 0, 0, 0, 4];
+            message.extend_from_slice(&2f32.to_bits().to_be_bytes());
+            message.push(1);
+            message.extend_from_slice(&[SET_KEY, 0, 0, 0, 0, 0, 0, 0, 8]);
+            message.extend_from_slice(&4f32.to_bits().to_be_bytes());
+            message.push(0);
+            message.extend_from_slice(&[DELETE_KEY, 0, 0, 0, 0, 0, 0, 0, 0]);
+            message.extend_from_slice(&[PAUSE, 1, SET_ROW, 0, 0, 0, 6]);
+            stream.write_all(&message).unwrap();
+
+            // Collect the rows reported by the client until it moves to row 10
+            let mut rows = Vec::new();
+            while rows.last() != Some(&10) {
+                let mut set_row = [0u8; 5];
+                stream.read_exact(&mut set_row).unwrap();
+                assert_eq!(SET_ROW, set_row[0]);
+                rows.push(u32::from_be_bytes(set_row[1..].try_into().unwrap()));
+            }
+            rows
+        });
+
+        let mut tracker = Tracker::new(120, None, &["foo"]).unwrap();
+        tracker.set_value("foo", 0, 1f32);
+        let mut client = Client::connect(address, &mut tracker).unwrap();
+        assert_eq!(None, tracker.tracks()[0].get_value(0));
+
+        let mut time_changed = false;
+        for _ in 0..100 {
+            time_changed |= client.update(&mut tracker).unwrap();
+            if tracker.time.is_paused() && tracker.current_row() == 6 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(time_changed);
+        assert!(tracker.time.is_paused());
+        assert_eq!(6, tracker.current_row());
+        assert_eq!(3f32, tracker.tracks()[0].value_at(6f32));
+
+        tracker.time.seek(tracker.get_time_from_row(10));
+        client.update(&mut tracker).unwrap();
+
+        // Rows set by the editor are not echoed back to it
+        let rows = editor.join().unwrap();
+        assert!(!rows.contains(&6));
+    }
+}
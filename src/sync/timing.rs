@@ -0,0 +1,196 @@
+//! Mapping between rows and time, and how rows are grouped into beats and bars.
+
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "editor")]
+use serde::{Deserialize, Serialize};
+
+use crate::binary;
+
+/// Switches to a new tempo from `row` and onwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
+pub struct TempoChange {
+    pub row: u32,
+    pub bpm: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "editor", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "editor", serde(default))]
+pub struct Timing {
+    /// The base tempo is given to the tracker in code, so it is not saved with the sync data
+    #[cfg_attr(feature = "editor", serde(skip))]
+    pub(super) bpm: u32,
+    pub(super) rows_per_beat: u32,
+    pub(super) beats_per_bar: u32,
+    /// Ordered by row
+    pub(super) tempo_changes: Vec<TempoChange>,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::new(120)
+    }
+}
+
+impl Timing {
+    pub(super) fn new(bpm: u32) -> Timing {
+        Timing {
+            bpm: bpm.max(1),
+            rows_per_beat: 4,
+            beats_per_bar: 4,
+            tempo_changes: Vec::new(),
+        }
+    }
+
+    pub fn bpm(&self) -> u32 {
+        self.bpm
+    }
+
+    pub fn rows_per_beat(&self) -> u32 {
+        self.rows_per_beat
+    }
+
+    pub fn beats_per_bar(&self) -> u32 {
+        self.beats_per_bar
+    }
+
+    pub fn rows_per_bar(&self) -> u32 {
+        self.rows_per_beat * self.beats_per_bar
+    }
+
+    pub fn tempo_changes(&self) -> &[TempoChange] {
+        &self.tempo_changes
+    }
+
+    /// Time in seconds at which a (fractional) row is reached.
+    pub fn seconds_at(&self, row: f32) -> f32 {
+        let mut seconds = 0f32;
+        let (mut start, mut bpm) = (0, self.bpm);
+        for change in &self.tempo_changes {
+            if change.row as f32 >= row {
+                break;
+            }
+            seconds += (change.row - start) as f32 / self.rows_per_second(bpm);
+            start = change.row;
+            bpm = change.bpm;
+        }
+        seconds + (row - start as f32) / self.rows_per_second(bpm)
+    }
+
+    /// The fractional row reached after a number of seconds.
+    pub fn row_at(&self, seconds: f32) -> f32 {
+        let mut elapsed = 0f32;
+        let (mut start, mut bpm) = (0, self.bpm);
+        for change in &self.tempo_changes {
+            let end = elapsed + (change.row - start) as f32 / self.rows_per_second(bpm);
+            if end > seconds {
+                break;
+            }
+            elapsed = end;
+            start = change.row;
+            bpm = change.bpm;
+        }
+        start as f32 + (seconds - elapsed) * self.rows_per_second(bpm)
+    }
+
+    pub(super) fn set_tempo_change(&mut self, row: u32, bpm: u32) {
+        let change = TempoChange { row, bpm };
+        match self.tempo_changes.binary_search_by_key(&row, |x| x.row) {
+            Ok(index) => self.tempo_changes[index] = change,
+            Err(index) => self.tempo_changes.insert(index, change),
+        }
+    }
+
+    /// Makes timing read from sync data usable, the same way the setters would have. Beats and bars
+    /// of no rows and tempos of no beats per minute are raised to one, and tempo changes are ordered
+    /// with only the first of any on the same row kept.
+    pub(super) fn sanitize(&mut self) {
+        self.bpm = self.bpm.max(1);
+        self.rows_per_beat = self.rows_per_beat.max(1);
+        self.beats_per_bar = self.beats_per_bar.max(1);
+        for change in &mut self.tempo_changes {
+            change.bpm = change.bpm.max(1);
+        }
+        self.tempo_changes.sort_by_key(|x| x.row);
+        self.tempo_changes.dedup_by_key(|x| x.row);
+    }
+
+    fn rows_per_second(&self, bpm: u32) -> f32 {
+        (bpm as f32 / 60f32) * self.rows_per_beat as f32
+    }
+
+    /// Reads the timing as written by version 2 and later of the binary format.
+    pub(super) fn read(mut reader: impl Read, bpm: u32) -> io::Result<Timing> {
+        let rows_per_beat = binary::read(&mut reader)?;
+        let beats_per_bar = binary::read(&mut reader)?;
+        let num_changes: u32 = binary::read(&mut reader)?;
+        let mut tempo_changes = Vec::new();
+        for _ in 0..num_changes {
+            tempo_changes.push(TempoChange {
+                row: binary::read(&mut reader)?,
+                bpm: binary::read(&mut reader)?,
+            });
+        }
+
+        let mut timing = Timing { bpm, rows_per_beat, beats_per_bar, tempo_changes };
+        timing.sanitize();
+        Ok(timing)
+    }
+
+    pub(super) fn write(&self, mut writer: impl Write) {
+        binary::write(&mut writer, &self.rows_per_beat);
+        binary::write(&mut writer, &self.beats_per_bar);
+        binary::write(&mut writer, &(self.tempo_changes.len() as u32));
+        for change in &self.tempo_changes {
+            binary::write(&mut writer, &change.row);
+            binary::write(&mut writer, &change.bpm);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_follow_rows_per_beat() {
+        let mut timing = Timing::new(120);
+        assert_eq!(0.5f32, timing.seconds_at(4f32));
+
+        // Triplets, three rows per beat
+        timing.rows_per_beat = 3;
+        assert_eq!(0.5f32, timing.seconds_at(3f32));
+        assert_eq!(6f32, timing.row_at(1f32));
+    }
+
+    #[test]
+    fn rows_follow_tempo_changes() {
+        let mut timing = Timing::new(120);
+        timing.set_tempo_change(16, 60);
+
+        // 16 rows at 8 rows per second, followed by 4 rows per second
+        assert_eq!(1f32, timing.seconds_at(8f32));
+        assert_eq!(2f32, timing.seconds_at(16f32));
+        assert_eq!(3f32, timing.seconds_at(20f32));
+
+        assert_eq!(8f32, timing.row_at(1f32));
+        assert_eq!(16f32, timing.row_at(2f32));
+        assert_eq!(20f32, timing.row_at(3f32));
+    }
+
+    #[test]
+    fn timing_read_is_made_usable() {
+        let mut timing = Timing::new(120);
+        timing.rows_per_beat = 0;
+        timing.tempo_changes = vec![TempoChange { row: 32, bpm: 0 }, TempoChange { row: 16, bpm: 60 }];
+        let mut data = Vec::new();
+        timing.write(&mut data);
+
+        let timing = Timing::read(&data[..], 0).unwrap();
+        assert_eq!((1, 1, 4), (timing.bpm(), timing.rows_per_beat(), timing.beats_per_bar()));
+        assert_eq!(&[TempoChange { row: 16, bpm: 60 }, TempoChange { row: 32, bpm: 1 }], timing.tempo_changes());
+        assert!(timing.seconds_at(40f32).is_finite());
+    }
+}
@@ -1,5 +1,16 @@
 use std::time::{Duration, Instant};
 
+use crate::DemoBuilder;
+
+impl DemoBuilder {
+    /// Renders every frame exactly `1 / fps` seconds after the previous one, however long it took,
+    /// so that the same frames are produced on every run. Music is not followed in this mode.
+    pub fn with_fixed_step(mut self, fps: u32) -> DemoBuilder {
+        self.demo.fixed_step = Some(FixedStepTimeSource::new(fps));
+        self
+    }
+}
+
 pub trait TimeSource {
     fn elapsed(&self) -> Duration;
 }
@@ -9,6 +20,8 @@ pub struct SeekableTimeSource {
     base: Instant,
     offset: Duration,
     paused: bool,
+    /// How fast time passes relative to real time
+    rate: f32,
 }
 
 impl TimeSource for SeekableTimeSource {
@@ -16,7 +29,7 @@ impl TimeSource for SeekableTimeSource {
         if self.paused {
             self.offset
         } else {
-            self.base.elapsed() + self.offset
+            self.base.elapsed().mul_f32(self.rate) + self.offset
         }
     }
 }
@@ -27,6 +40,7 @@ impl SeekableTimeSource {
             base: Instant::now(),
             offset: Duration::ZERO,
             paused: false,
+            rate: 1f32,
         }
     }
 
@@ -44,4 +58,96 @@ impl SeekableTimeSource {
         self.offset = pos;
         self.base = Instant::now();
     }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Changes the playback speed from now on, such as 0.5 for half speed or 0 to stop time. Panics
+    /// if the rate is negative or not finite.
+    pub fn set_rate(&mut self, rate: f32) {
+        assert!(rate.is_finite() && rate >= 0f32, "Invalid playback rate {}", rate);
+        self.offset = self.elapsed();
+        self.base = Instant::now();
+        self.rate = rate;
+    }
+}
+
+/// Time that only moves when told to, one frame at a time.
+#[derive(Clone)]
+pub struct FixedStepTimeSource {
+    fps: u32,
+    frame: u64,
+}
+
+impl TimeSource for FixedStepTimeSource {
+    fn elapsed(&self) -> Duration {
+        // Computed from the frame number rather than accumulated, so that no rounding error builds up
+        Duration::from_nanos((self.frame as u128 * 1_000_000_000 / self.fps as u128) as u64)
+    }
+}
+
+impl FixedStepTimeSource {
+    pub fn new(fps: u32) -> FixedStepTimeSource {
+        assert!(fps > 0, "The frame rate must be above zero");
+        FixedStepTimeSource { fps, frame: 0 }
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Moves on to the next frame.
+    pub fn advance(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn seek_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_step_advances_one_frame_at_a_time() {
+        let mut time = FixedStepTimeSource::new(60);
+        assert_eq!(Duration::ZERO, time.elapsed());
+
+        for _ in 0..60 {
+            time.advance();
+        }
+        assert_eq!(Duration::from_secs(1), time.elapsed());
+
+        time.seek_frame(3);
+        assert_eq!(Duration::from_nanos(50_000_000), time.elapsed());
+    }
+
+    #[test]
+    fn rate_changes_how_fast_time_passes() {
+        let mut time = SeekableTimeSource::now();
+        time.seek(Duration::from_secs(1));
+        time.set_rate(4f32);
+        std::thread::sleep(Duration::from_millis(50));
+
+        let elapsed = time.elapsed();
+        assert!(elapsed >= Duration::from_millis(1200));
+
+        // The time reached is kept when slowing down
+        time.set_rate(0f32);
+        assert!(time.elapsed() >= elapsed);
+        assert_eq!(time.elapsed(), time.elapsed());
+    }
+
+    #[test]
+    #[should_panic]
+    fn rate_can_not_be_negative() {
+        SeekableTimeSource::now().set_rate(-1f32);
+    }
 }
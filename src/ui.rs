@@ -1,22 +1,17 @@
-use std::{time::Duration, sync::{Arc, Mutex}};
+use std::time::Duration;
 
 use egui::FontDefinitions;
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::{Platform, PlatformDescriptor};
 use winit::window::Window;
 
-use crate::{DemoBuilder, sync, music::Music};
-
-impl DemoBuilder {
-    pub fn with_tracker(mut self, tracker: sync::Tracker) -> DemoBuilder {
-        self.demo.tracker = Some(tracker);
-        self
-    }
-}
+use crate::{sync, music::Music};
 
 pub struct Ui {
     platform: egui_winit_platform::Platform,
     pass: egui_wgpu_backend::RenderPass,
+    tracker_state: widgets::TrackerState,
+    curve_state: widgets::CurveState,
 }
 
 impl Ui {
@@ -43,6 +38,8 @@ impl Ui {
         Ui {
             platform,
             pass,
+            tracker_state: widgets::TrackerState::default(),
+            curve_state: widgets::CurveState::default(),
         }
     }
 
@@ -54,7 +51,7 @@ impl Ui {
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
         tracker: &mut Option<sync::Tracker>,
-        music: &mut Option<Arc<Mutex<Music>>>,
+        music: &mut Option<Music>,
     ) {
         let size = window.inner_size();
         let screen_descriptor = ScreenDescriptor {
@@ -66,12 +63,31 @@ impl Ui {
         self.platform.begin_frame();
         {
             let ctx = &self.platform.context();
+            let tracker_state = &mut self.tracker_state;
+            let curve_state = &mut self.curve_state;
 
             match tracker {
                 Some(ref mut tracker) => {
                     egui::Window::new("Tracker")
                         .show(ctx, |ui| {
-                            widgets::tracker_view(tracker, music, ui);
+                            // Release builds load the binary sync data from next to the editable file
+                            match tracker.path().map(|x| x.with_extension("bin")) {
+                                Some(path) => if ui.button(format!("Export {}", path.display())).clicked() {
+                                    match tracker.export(&path) {
+                                        Ok(_) => println!("Exported sync data to {}", path.display()),
+                                        Err(err) => println!("Failed to export sync data to {}: {}", path.display(), err),
+                                    }
+                                },
+                                None => (),
+                            }
+
+                            widgets::tracker_view(tracker, tracker_state, music, ui);
+                        });
+
+                    egui::Window::new("Curves")
+                        .default_open(false)
+                        .show(ctx, |ui| {
+                            widgets::curve_view(tracker, curve_state, ui);
                         });
                 },
                 None => (),
@@ -100,20 +116,86 @@ impl Ui {
 }
 
 pub mod widgets {
-    use std::{sync::{Arc, Mutex}};
+    use egui::{Ui, Grid, Key, Event, Color32, RichText, DragValue, plot::{Plot, Line, Points, Value, Values, VLine}};
 
-    use egui::{Ui, Grid, Key, Event, Color32, RichText};
+    use crate::{sync::{self, Interpolation, Selection}, music::Music, time::TimeSource};
 
-    use crate::{sync, music::Music};
+    /// Editing state of the tracker view that lives between frames.
+    pub struct TrackerState {
+        /// Index of the track the cursor is in, counting only the tracks that are shown
+        column: usize,
+        /// Number being typed into the cell under the cursor
+        input: String,
+        /// The (column, row) corner of the block selection opposite to the cursor
+        anchor: Option<(usize, u32)>,
+        clip: sync::Clip,
+        scale: f32,
+        offset: f32,
+        /// Name typed into the track editor
+        track_name: String,
+        /// Name typed into the marker editor
+        marker_name: String,
+    }
+
+    impl Default for TrackerState {
+        fn default() -> Self {
+            TrackerState {
+                column: 0,
+                input: String::new(),
+                anchor: None,
+                clip: sync::Clip::default(),
+                scale: 1f32,
+                offset: 0f32,
+                track_name: String::new(),
+                marker_name: String::new(),
+            }
+        }
+    }
+
+    impl TrackerState {
+        /// The selected block, or just the cell under the cursor if nothing is selected, where
+        /// `tracks` are the indices of the shown tracks.
+        fn selection(&self, row: u32, tracks: &[usize]) -> Selection {
+            Selection::between(tracks, self.anchor.unwrap_or((self.column, row)), (self.column, row))
+        }
+
+        /// The leftmost column of the selection.
+        fn first_column(&self) -> usize {
+            self.anchor.map_or(self.column, |(column, _)| std::cmp::min(column, self.column))
+        }
+
+        /// Starts a selection from the cursor when moving with alt held, or drops it otherwise.
+        fn before_move(&mut self, row: u32, select: bool) {
+            if !select {
+                self.anchor = None;
+            } else if self.anchor.is_none() {
+                self.anchor = Some((self.column, row));
+            }
+            self.input.clear();
+        }
+    }
 
     pub fn tracker_view(tracker: &mut sync::Tracker,
-        music: &mut Option<Arc<Mutex<Music>>>,
+        state: &mut TrackerState,
+        music: &mut Option<Music>,
         ui: &mut Ui
     ) {
         let mut row = tracker.current_row() as i32;
-        {
+        let rows_per_beat = tracker.timing().rows_per_beat() as i32;
+        let rows_per_bar = tracker.timing().rows_per_bar() as i32;
+        let tracks = columns(tracker)
+            .into_iter()
+            .filter_map(|x| match x { Column::Track(index) => Some(index), Column::Collapsed(_) => None })
+            .collect::<Vec<usize>>();
+        state.column = std::cmp::min(state.column, tracks.len().saturating_sub(1));
+        let column_name = |tracker: &sync::Tracker, column: usize| {
+            tracks.get(column).map(|x| tracker.tracks()[*x].name().to_string())
+        };
+        // Keys go to text fields, such as the one for track names, while they have focus
+        if !ui.ctx().wants_keyboard_input() {
             let events = &ui.input().events;
             for event in events {
+                let selection = state.selection(row as u32, &tracks);
                 match event {
                     Event::Key {
                         key: Key::Space,
@@ -124,9 +206,8 @@ pub mod widgets {
 
                         match music {
                             Some(music) => {
-                                let mut music = music.as_ref().lock().unwrap();
-                                music.paused = tracker.time.is_paused();
-                                if !music.paused {
+                                music.set_paused(tracker.time.is_paused());
+                                if !music.is_paused() {
                                     music.seek(&tracker.get_time_from_row(row as u32));
                                 }
                             },
@@ -138,34 +219,276 @@ pub mod widgets {
                         pressed: true,
                         modifiers,
                     } => {
-                        row = std::cmp::max(0, row - if modifiers.shift { 4 } else { 1 });
-                        tracker.time.seek(tracker.get_time_from_row(row as u32));
+                        state.before_move(row as u32, modifiers.alt);
+                        row = std::cmp::max(0, row - if modifiers.shift { rows_per_beat } else { 1 });
                         tracker.time.set_paused(true);
+                        seek(tracker, music, row as u32);
                     }
                     Event::Key {
                         key: Key::ArrowDown,
                         pressed: true,
                         modifiers,
                     } => {
-                        row += if modifiers.shift { 4 } else { 1 };
-                        tracker.time.seek(tracker.get_time_from_row(row as u32));
+                        state.before_move(row as u32, modifiers.alt);
+                        row += if modifiers.shift { rows_per_beat } else { 1 };
                         tracker.time.set_paused(true);
+                        seek(tracker, music, row as u32);
+                    }
+                    Event::Key {
+                        key: Key::PageUp,
+                        pressed: true,
+                        modifiers,
+                    } => {
+                        state.before_move(row as u32, modifiers.alt);
+                        row = if modifiers.command {
+                            tracker.previous_marker(row as u32).map_or(0, |x| x.row as i32)
+                        } else {
+                            // To the start of the bar, or the one before if already there
+                            std::cmp::max(0, (row - 1).div_euclid(rows_per_bar) * rows_per_bar)
+                        };
+                        seek(tracker, music, row as u32);
+                    }
+                    Event::Key {
+                        key: Key::PageDown,
+                        pressed: true,
+                        modifiers,
+                    } => {
+                        state.before_move(row as u32, modifiers.alt);
+                        row = if modifiers.command {
+                            tracker.next_marker(row as u32).map_or(row, |x| x.row as i32)
+                        } else {
+                            (row / rows_per_bar + 1) * rows_per_bar
+                        };
+                        seek(tracker, music, row as u32);
+                    }
+                    Event::Key {
+                        key: Key::ArrowLeft,
+                        pressed: true,
+                        modifiers,
+                    } => {
+                        state.before_move(row as u32, modifiers.alt);
+                        state.column = state.column.saturating_sub(1);
+                    }
+                    Event::Key {
+                        key: Key::ArrowRight,
+                        pressed: true,
+                        modifiers,
+                    } => {
+                        state.before_move(row as u32, modifiers.alt);
+                        state.column = std::cmp::min(state.column + 1, tracks.len().saturating_sub(1));
+                    }
+                    Event::Text(text) => {
+                        state.input.extend(text.chars().filter(|x| x.is_ascii_digit() || *x == '.' || *x == '-'));
+                    }
+                    Event::Key {
+                        key: Key::Backspace,
+                        pressed: true,
+                        modifiers: _,
+                    } => {
+                        state.input.pop();
+                    }
+                    Event::Key {
+                        key: Key::Enter,
+                        pressed: true,
+                        modifiers: _,
+                    } => {
+                        match (column_name(tracker, state.column), state.input.parse::<f32>()) {
+                            (Some(name), Ok(value)) => tracker.set_value(&name, row as u32, value),
+                            (_, Err(_)) if !state.input.is_empty() => println!("{} is not a valid value", state.input),
+                            _ => (),
+                        }
+                        state.input.clear();
+                    }
+                    Event::Copy
+                    | Event::Key {
+                        key: Key::Insert,
+                        pressed: true,
+                        modifiers: egui::Modifiers { command: true, .. },
+                    } => {
+                        state.clip = tracker.copy(&selection);
+                    }
+                    Event::Cut
+                    | Event::Key {
+                        key: Key::Delete,
+                        pressed: true,
+                        modifiers: egui::Modifiers { shift: true, .. },
+                    } => {
+                        state.clip = tracker.cut(&selection);
+                    }
+                    Event::Key {
+                        key: Key::V,
+                        pressed: true,
+                        modifiers: egui::Modifiers { command: true, .. },
+                    }
+                    | Event::Key {
+                        key: Key::Insert,
+                        pressed: true,
+                        modifiers: egui::Modifiers { shift: true, .. },
+                    } => {
+                        let first = std::cmp::min(state.first_column(), tracks.len());
+                        tracker.paste(&tracks[first..], selection.rows.start, &state.clip);
+                    }
+                    Event::Key {
+                        key: Key::Insert,
+                        pressed: true,
+                        modifiers: _,
+                    } => {
+                        tracker.insert_rows(&selection.tracks, selection.rows.start, selection.rows.len() as u32);
+                    }
+                    Event::Key {
+                        key: Key::Delete,
+                        pressed: true,
+                        modifiers,
+                    } => {
+                        if modifiers.command {
+                            tracker.delete_rows(&selection.tracks, selection.rows.start, selection.rows.len() as u32);
+                        } else {
+                            tracker.clear(&selection);
+                        }
+                        state.input.clear();
+                    }
+                    Event::Key {
+                        key: Key::Z,
+                        pressed: true,
+                        modifiers,
+                    } if modifiers.command => {
+                        if modifiers.shift { tracker.redo(); } else { tracker.undo(); }
+                        state.input.clear();
+                    }
+                    Event::Key {
+                        key: Key::Y,
+                        pressed: true,
+                        modifiers,
+                    } if modifiers.command => {
+                        tracker.redo();
+                        state.input.clear();
+                    }
+                    Event::Key {
+                        key: Key::L,
+                        pressed: true,
+                        modifiers,
+                    } => {
+                        if modifiers.command {
+                            tracker.set_loop(0..0);
+                        } else if state.anchor.is_some() {
+                            tracker.set_loop(selection.rows.clone());
+                        } else if modifiers.shift {
+                            tracker.set_loop_out(row as u32);
+                        } else {
+                            tracker.set_loop_in(row as u32);
+                        }
+                    }
+                    Event::Key {
+                        key: Key::I,
+                        pressed: true,
+                        modifiers: _,
+                    } => {
+                        match column_name(tracker, state.column) {
+                            Some(name) => {
+                                match tracker.tracks()[tracks[state.column]].get_interpolation(row as u32) {
+                                    Some(interpolation) => tracker.set_interpolation(&name, row as u32, interpolation.next()),
+                                    None => (),
+                                }
+                            },
+                            None => (),
+                        }
                     }
                     _ => ()
                 }
             }
         }
 
-        let tracks = tracker.tracks();
+        let selection = state.selection(row as u32, &tracks);
+
+        ui.collapsing("Timing", |ui| {
+            timing_editor(tracker, row as u32, ui);
+        });
+
+        ui.collapsing("Tracks", |ui| {
+            let current = column_name(tracker, state.column);
+            track_editor(tracker, current, &mut state.track_name, ui);
+        });
+
+        ui.collapsing("Groups", |ui| {
+            group_editor(tracker, ui);
+        });
+
+        ui.collapsing("Markers", |ui| {
+            match marker_editor(tracker, row as u32, &mut state.marker_name, ui) {
+                Some(to_row) => {
+                    row = to_row as i32;
+                    seek(tracker, music, to_row);
+                },
+                None => (),
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Scale");
+            ui.add(DragValue::new(&mut state.scale).speed(0.01));
+            ui.label("Offset");
+            ui.add(DragValue::new(&mut state.offset).speed(0.01));
+            if ui.button("Apply to selection").clicked() {
+                tracker.scale_values(&selection, state.scale, state.offset);
+            }
+        });
+        ui.label("Hold Alt while moving the cursor to select a block");
+
+        ui.horizontal(|ui| {
+            ui.label("Speed");
+            for rate in [0.25f32, 0.5f32, 1f32, 2f32] {
+                if ui.selectable_label(tracker.time.rate() == rate, format!("{}x", rate)).clicked() {
+                    set_rate(tracker, music, rate);
+                }
+            }
+        });
+
+        // L sets the loop start or loops the selected rows, Shift+L sets the end and Ctrl+L clears it
+        match tracker.loop_region() {
+            Some(region) => ui.label(format!("Looping rows {:04} to {:04}", region.start, region.end - 1)),
+            None => ui.label("Not looping"),
+        };
+
+        // Groups may have been changed above
+        let columns = columns(tracker);
+        let groups = tracker.groups();
+        let all_tracks = tracker.tracks();
+        let loop_region = tracker.loop_region();
+        let mut toggled = None;
 
         Grid::new("tracker_view")
-            .num_columns(tracks.len() + 1)
+            .num_columns(columns.len() + 1)
             .striped(true)
             .show(ui, |ui| {
-                // Headings
+                // Headings, with the group above the first track of each group
+                ui.label("");
+                for (index, column) in columns.iter().enumerate() {
+                    let group = match column {
+                        Column::Track(track) => groups.iter().position(|x| x.name() == all_tracks[*track].group()).unwrap(),
+                        Column::Collapsed(group) => *group,
+                    };
+                    let first = match (index.checked_sub(1).map(|x| &columns[x]), column) {
+                        (Some(Column::Track(previous)), Column::Track(track)) => all_tracks[*previous].group() != all_tracks[*track].group(),
+                        _ => true,
+                    };
+
+                    if !first || groups[group].name().is_empty() {
+                        ui.label("");
+                    } else {
+                        let arrow = if groups[group].is_collapsed() { "▶" } else { "▼" };
+                        if ui.small_button(format!("{} {}", arrow, groups[group].name())).clicked() {
+                            toggled = Some(group);
+                        }
+                    }
+                }
+                ui.end_row();
+
                 ui.label(RichText::new("Beat").strong());
-                for track in tracks {
-                    ui.label(RichText::new(track.name()).strong());
+                for column in &columns {
+                    match column {
+                        Column::Track(track) => ui.label(RichText::new(all_tracks[*track].short_name()).strong()),
+                        Column::Collapsed(_) => ui.label(""),
+                    };
                 }
                 ui.end_row();
 
@@ -176,29 +499,362 @@ pub mod widgets {
                         continue;
                     }
 
-                    let label = if n == row { format!("{:04} >", n) } else { format!("{:04}", n) };
-                    if n % 4 == 0 {
-                        ui.colored_label(Color32::RED, label);
-                    } else {
-                        ui.label(label);
+                    let mut label = if n == row { format!("{:04} >", n) } else { format!("{:04}", n) };
+                    match tracker.marker_at(n as u32) {
+                        Some(marker) => label = format!("{} {}", label, marker.name),
+                        None => (),
                     }
+                    let mut label = RichText::new(label);
+                    if n % rows_per_bar == 0 {
+                        label = label.color(Color32::RED).strong();
+                    } else if n % rows_per_beat == 0 {
+                        label = label.color(Color32::LIGHT_RED);
+                    }
+                    if matches!(&loop_region, Some(region) if region.contains(&(n as u32))) {
+                        label = label.background_color(Color32::DARK_GREEN);
+                    }
+                    ui.label(label);
+
+                    for column in &columns {
+                        let (index, track) = match column {
+                            Column::Track(index) => (*index, &all_tracks[*index]),
+                            Column::Collapsed(_) => {
+                                ui.label("");
+                                continue;
+                            },
+                        };
+                        let text = match track.get_value(n as u32) {
+                            Some(value) => format!("{}{}", value, interpolation_symbol(track.get_interpolation(n as u32).unwrap())),
+                            None => "...".to_string(),
+                        };
 
-                    for track in tracks {
-                        if n == row {
+                        if n == row && tracks.get(state.column) == Some(&index) {
+                            let text = if state.input.is_empty() { text } else { format!("{}_", state.input) };
+                            ui.label(RichText::new(text).color(Color32::BLACK).background_color(Color32::LIGHT_BLUE));
+                        } else if state.anchor.is_some() && selection.contains(index, n as u32) {
+                            ui.label(RichText::new(text).background_color(Color32::DARK_BLUE));
+                        } else if n == row {
                             match track.get_value(n as u32) {
-                                Some(value) => ui.label(RichText::new(format!("{}", value)).background_color(Color32::GRAY)),
-                                None => ui.label(RichText::new("...").background_color(Color32::LIGHT_GRAY)),
+                                Some(_) => ui.label(RichText::new(text).background_color(Color32::GRAY)),
+                                None => ui.label(RichText::new(text).background_color(Color32::LIGHT_GRAY)),
                             };
                         } else {
-                            match track.get_value(n as u32) {
-                                Some(value) => ui.label(format!("{}", value)),
-                                None => ui.label("..."),
-                            };
+                            ui.label(text);
                         }
                     }
 
                     ui.end_row();
                 }
             });
+
+        match toggled {
+            Some(group) => {
+                let group = &tracker.groups()[group];
+                let (name, collapsed) = (group.name().to_string(), group.is_collapsed());
+                tracker.set_group_collapsed(&name, !collapsed);
+            },
+            None => (),
+        }
+    }
+
+    /// Moves both the tracker and the music to `row`.
+    fn seek(tracker: &mut sync::Tracker, music: &mut Option<Music>, row: u32) {
+        let time = tracker.get_time_from_row(row);
+        tracker.time.seek(time);
+
+        match music {
+            Some(music) => {
+                music.set_paused(tracker.time.is_paused());
+                music.seek(&time);
+            },
+            None => (),
+        }
+    }
+
+    /// Changes the playback speed of both the tracker and the music, keeping them in sync.
+    fn set_rate(tracker: &mut sync::Tracker, music: &mut Option<Music>, rate: f32) {
+        tracker.time.set_rate(rate);
+
+        match music {
+            Some(music) => {
+                music.set_rate(rate);
+                music.seek(&tracker.time.elapsed());
+            },
+            None => (),
+        }
+    }
+
+    /// Lists the markers and adds new ones at `row`. Returns the row of a marker that was clicked.
+    fn marker_editor(tracker: &mut sync::Tracker, row: u32, name: &mut String, ui: &mut Ui) -> Option<u32> {
+        let mut jump = None;
+        for marker in tracker.markers().to_vec() {
+            ui.horizontal(|ui| {
+                if ui.button(format!("{:04} {}", marker.row, marker.name)).clicked() {
+                    jump = Some(marker.row);
+                }
+                if ui.button("Remove").clicked() {
+                    tracker.remove_marker(marker.row);
+                }
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(name);
+            if ui.add_enabled(!name.trim().is_empty(), egui::Button::new(format!("Mark row {:04}", row))).clicked() {
+                tracker.set_marker(row, name.trim());
+                name.clear();
+            }
+        });
+        ui.label("Ctrl+PageUp and Ctrl+PageDown jump between markers");
+        jump
+    }
+
+    /// A column of the tracker grid, either a track or a collapsed group in place of its tracks.
+    enum Column {
+        Track(usize),
+        Collapsed(usize),
+    }
+
+    /// The columns of the groups that are not hidden, in order.
+    fn columns(tracker: &sync::Tracker) -> Vec<Column> {
+        let mut columns = Vec::new();
+        for (index, group) in tracker.groups().iter().enumerate() {
+            let mut tracks = tracker.tracks()
+                .iter()
+                .enumerate()
+                .filter(|(_, track)| track.group() == group.name())
+                .map(|(track, _)| Column::Track(track))
+                .peekable();
+
+            if group.is_hidden() || tracks.peek().is_none() {
+                continue;
+            } else if group.is_collapsed() {
+                columns.push(Column::Collapsed(index));
+            } else {
+                columns.extend(tracks);
+            }
+        }
+        columns
+    }
+
+    /// Adding tracks, and renaming or removing the one under the cursor.
+    fn track_editor(tracker: &mut sync::Tracker, current: Option<String>, name: &mut String, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(name).hint_text("group:name"));
+            if ui.button("Add").clicked() {
+                if tracker.add_track(name.trim()) {
+                    name.clear();
+                } else {
+                    println!("A track named {} already exists", name.trim());
+                }
+            }
+            match current {
+                Some(current) => {
+                    if ui.button(format!("Rename {}", current)).clicked() {
+                        if tracker.rename_track(&current, name.trim()) {
+                            name.clear();
+                        } else {
+                            println!("Failed to rename {} to {}", current, name.trim());
+                        }
+                    }
+                    if ui.button(format!("Remove {}", current)).clicked() {
+                        tracker.remove_track(&current);
+                    }
+                },
+                None => (),
+            }
+        });
+
+        // Orphaned tracks keep their keys until they are added back or the sync file is cleaned up
+        let orphans = tracker.orphaned_tracks().iter().map(|x| x.name().to_string()).collect::<Vec<String>>();
+        for orphan in orphans {
+            ui.horizontal(|ui| {
+                ui.label(format!("Unused track {}", orphan));
+                if ui.button("Restore").clicked() {
+                    tracker.add_track(orphan);
+                }
+            });
+        }
+    }
+
+    /// Visibility and order of the track groups.
+    fn group_editor(tracker: &mut sync::Tracker, ui: &mut Ui) {
+        let groups = tracker.groups().to_vec();
+        for (index, group) in groups.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let name = if group.name().is_empty() { "(ungrouped)" } else { group.name() };
+                let mut visible = !group.is_hidden();
+                if ui.checkbox(&mut visible, name).changed() {
+                    tracker.set_group_hidden(group.name(), !visible);
+                }
+                if ui.add_enabled(index > 0, egui::Button::new("Up").small()).clicked() {
+                    tracker.move_group(group.name(), -1);
+                }
+                if ui.add_enabled(index + 1 < groups.len(), egui::Button::new("Down").small()).clicked() {
+                    tracker.move_group(group.name(), 1);
+                }
+            });
+        }
+    }
+
+    /// Rows per beat, beats per bar and tempo changes, where new tempo changes are added at `row`.
+    fn timing_editor(tracker: &mut sync::Tracker, row: u32, ui: &mut Ui) {
+        let mut rows_per_beat = tracker.timing().rows_per_beat();
+        let mut beats_per_bar = tracker.timing().beats_per_bar();
+        ui.horizontal(|ui| {
+            if ui.add(DragValue::new(&mut rows_per_beat).clamp_range(1..=64).prefix("Rows per beat: ")).changed() {
+                tracker.set_rows_per_beat(rows_per_beat);
+            }
+            if ui.add(DragValue::new(&mut beats_per_bar).clamp_range(1..=64).prefix("Beats per bar: ")).changed() {
+                tracker.set_beats_per_bar(beats_per_bar);
+            }
+        });
+
+        ui.label(format!("Base tempo: {} BPM", tracker.timing().bpm()));
+        for change in tracker.timing().tempo_changes().to_vec() {
+            ui.horizontal(|ui| {
+                let mut bpm = change.bpm;
+                ui.label(format!("Row {:04}", change.row));
+                if ui.add(DragValue::new(&mut bpm).clamp_range(1..=999).suffix(" BPM")).changed() {
+                    tracker.set_tempo_change(change.row, bpm);
+                }
+                if ui.button("Remove").clicked() {
+                    tracker.remove_tempo_change(change.row);
+                }
+            });
+        }
+        if ui.button(format!("Change tempo at row {:04}", row)).clicked() {
+            let bpm = tracker.timing().bpm();
+            tracker.set_tempo_change(row, bpm);
+        }
+    }
+
+    fn interpolation_symbol(interpolation: Interpolation) -> &'static str {
+        match interpolation {
+            Interpolation::Step => "",
+            Interpolation::Linear => " /",
+            Interpolation::Smooth => " ~",
+            Interpolation::Ramp => " ^",
+        }
+    }
+
+    /// State of the curve view that lives between frames.
+    pub struct CurveState {
+        /// Names of the plotted tracks
+        visible: Vec<String>,
+        /// How many rows around the playhead to plot
+        rows: u32,
+        /// The (track, row) of the key being dragged
+        dragging: Option<(usize, u32)>,
+    }
+
+    impl Default for CurveState {
+        fn default() -> Self {
+            CurveState {
+                visible: Vec::new(),
+                rows: 64,
+                dragging: None,
+            }
+        }
+    }
+
+    /// Plots tracks as curves around the playhead, with keys that can be dragged to new rows and values.
+    pub fn curve_view(tracker: &mut sync::Tracker, state: &mut CurveState, ui: &mut Ui) {
+        let row = tracker.current_row();
+
+        ui.horizontal_wrapped(|ui| {
+            for track in tracker.tracks() {
+                let mut visible = state.visible.iter().any(|x| x == track.name());
+                if ui.checkbox(&mut visible, track.name()).changed() {
+                    if visible {
+                        state.visible.push(track.name().to_string());
+                    } else {
+                        state.visible.retain(|x| x != track.name());
+                    }
+                }
+            }
+            ui.add(DragValue::new(&mut state.rows).clamp_range(8..=1024).prefix("Rows: "));
+        });
+
+        let start = row.saturating_sub(state.rows / 2);
+        let end = start + state.rows;
+        let visible = tracker.tracks()
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| state.visible.iter().any(|x| x == track.name()))
+            .collect::<Vec<(usize, &sync::Track)>>();
+
+        let dropped = Plot::new("curve_view")
+            .allow_drag(false)
+            .include_x(start as f64)
+            .include_x(end as f64)
+            .view_aspect(2f32)
+            .show(ui, |plot_ui| {
+                for (_, track) in &visible {
+                    // Sample between the rows to show the shape of the interpolation
+                    plot_ui.line(Line::new(Values::from_values_iter((start * 4..=end * 4).map(|x| {
+                        let row = x as f32 / 4f32;
+                        Value::new(row, track.value_at(row))
+                    }))).name(track.name()));
+
+                    plot_ui.points(Points::new(Values::from_values(track.keys()
+                        .iter()
+                        .filter(|x| (start..=end).contains(&x.row()))
+                        .map(|x| Value::new(x.row(), x.value()))
+                        .collect()))
+                        .radius(4f32)
+                        .name(track.name()));
+                }
+                plot_ui.vline(VLine::new(row).color(Color32::RED));
+
+                let (pressed, down) = {
+                    let input = plot_ui.ctx().input();
+                    (input.pointer.any_pressed() && input.pointer.primary_down(), input.pointer.primary_down())
+                };
+
+                match (state.dragging, plot_ui.pointer_coordinate()) {
+                    (None, Some(pointer)) if pressed && plot_ui.plot_hovered() => {
+                        // Pick up the key closest to the pointer, if it is close enough on screen
+                        let pointer = plot_ui.screen_from_plot(pointer);
+                        state.dragging = visible
+                            .iter()
+                            .flat_map(|(index, track)| track.keys().iter().map(move |key| (*index, *key)))
+                            .map(|(index, key)| {
+                                let position = plot_ui.screen_from_plot(Value::new(key.row(), key.value()));
+                                (index, key.row(), position.distance(pointer))
+                            })
+                            .filter(|(_, _, distance)| *distance < 8f32)
+                            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap())
+                            .map(|(index, row, _)| (index, row));
+                        None
+                    },
+                    (Some((track, from_row)), Some(pointer)) => {
+                        let to_row = pointer.x.round().max(0f64) as u32;
+                        if down {
+                            plot_ui.points(Points::new(Values::from_values(vec![Value::new(to_row, pointer.y)]))
+                                .radius(5f32)
+                                .color(Color32::WHITE));
+                            None
+                        } else {
+                            Some((track, from_row, to_row, pointer.y as f32))
+                        }
+                    },
+                    (Some(_), None) => {
+                        state.dragging = None;
+                        None
+                    },
+                    (None, _) => None,
+                }
+            })
+            .inner;
+
+        match dropped {
+            Some((track, from_row, to_row, value)) => {
+                state.dragging = None;
+                let name = tracker.tracks()[track].name().to_string();
+                tracker.move_key(&name, from_row, to_row, value);
+            },
+            None => (),
+        }
     }
 }